@@ -41,23 +41,56 @@
 // # pentru TLS nativ
 // axum-server = { version = "0.6", features = ["tls-rustls"] }
 //
+// # pentru transcodare/thumbnails la cerere
+// image = { version = "0.25", default-features = false, features = ["png", "jpeg", "gif", "webp"] }
+//
+// # pentru criptare la rest (opțional, vezi ENCRYPT_AT_REST)
+// aes-gcm-siv = "0.11"
+// hkdf = "0.12"
+//
+// # pentru 2FA TOTP (RFC 6238)
+// sha1 = "0.10"
+//
+// # pentru trimitere email (invitații + verificare cont)
+// lettre = { version = "0.11", default-features = false, features = ["tokio1-rustls-tls", "smtp-transport", "builder"] }
+//
+// # pentru trait-ul Store (dyn-compatible) + backend SQLite opțional
+// async-trait = "0.1"
+// sqlx = { version = "0.7", default-features = false, features = ["runtime-tokio", "sqlite"] }
+//
+// # pentru diagnostics (spațiu liber pe disc) + backup (arhivare zip)
+// fs4 = "0.9"
+// zip = { version = "2", default-features = false, features = ["deflate"] }
+//
 // Build & run:
 //   cargo run --release
 //
 
 use axum::{
     body::Body,
-    extract::{Multipart, Path, State},
+    extract::{Multipart, Path, Query, State},
     http::{header, HeaderMap, HeaderValue, StatusCode},
     response::{Html, IntoResponse, Response},
     routing::{delete, get, post},
     Json, Router,
 };
+use aes_gcm_siv::{
+    aead::{Aead, KeyInit},
+    Aes256GcmSiv, Key, Nonce,
+};
+use async_trait::async_trait;
+use hkdf::Hkdf;
 use hmac::{Hmac, Mac};
+use lettre::{
+    message::Mailbox, transport::smtp::authentication::Credentials, AsyncSmtpTransport,
+    AsyncTransport, Message, Tokio1Executor,
+};
 use parking_lot::Mutex;
 use rand::RngCore;
 use serde::{Deserialize, Serialize};
+use sha1::Sha1;
 use sha2::Sha256;
+use sqlx::{sqlite::SqlitePoolOptions, Row, SqlitePool};
 
 use axum_server::tls_rustls::RustlsConfig;
 use axum::{response::Redirect, extract::{Host, OriginalUri}};
@@ -67,9 +100,10 @@ use std::{
     fs,
     io::{self, Write},
     net::SocketAddr,
+    os::unix::io::FromRawFd, // pentru preluarea fd-urilor moștenite la systemd socket-activation
     path::{Path as FsPath, PathBuf},
     sync::Arc,
-    time::{SystemTime, UNIX_EPOCH},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 use tokio::{fs as tfs, io::AsyncWriteExt, signal};
 use tokio_util::io::ReaderStream;
@@ -87,7 +121,19 @@ const DEFAULT_MAX_UPLOAD_BYTES: u64 = 10 * 1024 * 1024;
 const DEFAULT_RATE_TOKENS: f32 = 20.0;
 const DEFAULT_RATE_REFILL: f32 = 1.0; // tokens/sec
 const SESSION_MAX_AGE_MS: u64 = 30 * 24 * 60 * 60 * 1000; // 30 zile
+const DEFAULT_SHUTDOWN_GRACE_SECS: u64 = 30; // timp de grație pt. drenarea conexiunilor la shutdown
+const DEFAULT_TLS_RELOAD_INTERVAL_SECS: u64 = 30; // interval de poll pt. detectarea reînnoirii certificatului TLS
+const UPLOAD_JWT_TTL_SECS: u64 = 24 * 60 * 60; // token de upload emis per .sxcu, expiră în 24h
 const DEFAULT_BG_COLOR: &str = "#05080f";
+const DEFAULT_ALLOWED_UPLOAD_MIMES: &[&str] = &[
+    "image/gif",
+    "image/jpeg",
+    "image/png",
+    "image/webp",
+    "video/mp4",
+    "video/webm",
+    "application/pdf",
+];
 
 // ========================= Tipuri persistente =========================
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -96,8 +142,16 @@ struct BackgroundPref {
     kind: String,
     value: String,
 }
+impl Default for BackgroundPref {
+    fn default() -> Self {
+        Self {
+            kind: "color".into(),
+            value: DEFAULT_BG_COLOR.into(),
+        }
+    }
+}
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 struct Preferences {
     background: BackgroundPref,
 }
@@ -111,7 +165,12 @@ struct User {
     created_at: u64,
     role: String,
     preferences: Preferences,
-    images: Vec<ImageMeta>,
+    totp_secret: Option<String>,
+    // secretul generat de api_2fa_enable, neactivat până când api_2fa_verify confirmă un cod
+    // valid — altfel un apel /enable fără /verify ar activa imediat 2FA și ar bloca userul afară
+    pending_totp_secret: Option<String>,
+    // conturile existente înainte de verificarea prin email rămân verificate implicit
+    verified: bool,
 }
 impl Default for User {
     fn default() -> Self {
@@ -121,13 +180,10 @@ impl Default for User {
             email: String::new(),
             created_at: now_ms(),
             role: "user".into(),
-            preferences: Preferences {
-                background: BackgroundPref {
-                    kind: "color".into(),
-                    value: DEFAULT_BG_COLOR.into(),
-                },
-            },
-            images: vec![],
+            preferences: Preferences::default(),
+            totp_secret: None,
+            pending_totp_secret: None,
+            verified: true,
         }
     }
 }
@@ -141,6 +197,68 @@ struct ImageMeta {
     url: String,
     uploaded_at: u64,
     owner: Option<String>,
+    #[serde(default)]
+    sniffed_mime: Option<String>,
+    #[serde(default)]
+    embed: EmbedMeta,
+    #[serde(default)]
+    sensitive: bool,
+    #[serde(default)]
+    content_warning: Option<String>,
+    #[serde(default)]
+    alt_text: Option<String>,
+    #[serde(default = "default_visibility")]
+    visibility: String,
+    #[serde(default)]
+    encrypted: bool,
+    #[serde(default)]
+    enc_nonce: Option<String>,
+}
+
+fn default_visibility() -> String {
+    "public".to_string()
+}
+
+/// ce tip de card OpenGraph/oEmbed se redă pentru un fișier; `None` = autodetectat după tipul fișierului
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum EmbedKind {
+    Website,
+    Image,
+    Video,
+    None,
+}
+impl Default for EmbedKind {
+    fn default() -> Self {
+        EmbedKind::None
+    }
+}
+
+fn parse_embed_kind(s: &str) -> EmbedKind {
+    match s.to_ascii_lowercase().as_str() {
+        "website" => EmbedKind::Website,
+        "image" => EmbedKind::Image,
+        "video" => EmbedKind::Video,
+        _ => EmbedKind::None,
+    }
+}
+
+/// metadate de embed per-imagine, personalizabile prin `api_images_update_embed`;
+/// câmpurile lipsă cad pe defaulturile curente (filename ca titlu etc.)
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+struct EmbedMeta {
+    #[serde(default)]
+    kind: EmbedKind,
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    author_name: Option<String>,
+    #[serde(default)]
+    author_url: Option<String>,
+    #[serde(default)]
+    theme_color: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -153,6 +271,21 @@ struct ImagesJson {
     images: Vec<ImageMeta>,
 }
 
+/// cont creat de admin, în așteptarea acceptării invitației prin email
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+struct Invitation {
+    username: String,
+    email: String,
+    token_hash: String,
+    created_at: u64,
+    expires_at: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+struct InvitationsJson {
+    invitations: Vec<Invitation>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct SettingsJson {
     registerBlocked: bool,
@@ -178,11 +311,30 @@ struct Config {
     users_file: PathBuf,
     images_file: PathBuf,
     settings_file: PathBuf,
+    invitations_file: PathBuf,
     max_upload_bytes: u64,
     rate_tokens: f32,
     rate_refill: f32,
     ssl_key_path: Option<PathBuf>,
     ssl_cert_path: Option<PathBuf>,
+    allowed_upload_mimes: Vec<String>,
+    encrypt_at_rest: bool,
+    smtp_host: Option<String>,
+    smtp_port: Option<u16>,
+    smtp_user: Option<String>,
+    smtp_pass: Option<String>,
+    smtp_from: Option<String>,
+    admin_token: Option<String>, // dacă e setat, /api/admin/* cere în plus Authorization: Bearer <ADMIN_TOKEN>
+    shutdown_grace_secs: u64, // timp maxim de drenare a conexiunilor active la shutdown
+    tls_hot_reload: bool, // dacă e activ, un task de fundal re-încarcă certificatul când se schimbă pe disc
+    tls_reload_interval_secs: u64, // interval de poll al mtime-ului pt. SSL_CERT_PATH/SSL_KEY_PATH
+    redirect_permanent: bool, // 301 (permanent) în loc de 307 (temporar, implicit) pt. redirectul HTTP -> HTTPS
+    trust_forwarded_host: bool, // onorează X-Forwarded-Host (pt. deploy-uri în spatele altui proxy)
+}
+
+/// SMTP e configurat doar dacă avem minimum un host și o adresă "from"
+fn smtp_configured(cfg: &Config) -> bool {
+    cfg.smtp_host.is_some() && cfg.smtp_from.is_some()
 }
 
 #[derive(Default)]
@@ -200,10 +352,471 @@ struct AppState {
     initial_upload_token_plain: Arc<Mutex<Option<String>>>,
     // first-run admin password (optional)
     initial_admin_pass_plain: Arc<Mutex<Option<String>>>,
-    users: Arc<Mutex<UsersJson>>,   // în memorie + persist pe disc
-    images: Arc<Mutex<ImagesJson>>, // în memorie + persist pe disc
-    settings: Arc<Mutex<SettingsJson>>, // register lock
+    store: Arc<dyn Store>, // backend de persistență (JSON pe disc sau SQLite)
+    invitations: Arc<Mutex<InvitationsJson>>, // conturi create de admin, în așteptarea acceptării
     rate: Arc<Mutex<HashMap<String, RateBucket>>>,
+    started_at_ms: u64, // pentru /api/admin/diagnostics (uptime)
+}
+
+// ========================= Store (persistență pluggabilă) =========================
+// abstractizează users/images/settings în spatele unui trait async, ca să putem
+// alege la runtime între fișiere JSON (comportamentul istoric) și SQLite, fără
+// ca handlerele să știe care dintre ele e activă
+#[async_trait]
+trait Store: Send + Sync {
+    async fn list_users(&self) -> Vec<User>;
+    async fn find_user(&self, username: &str) -> Option<User>;
+    async fn find_user_by_email(&self, email: &str) -> Option<User>;
+    async fn upsert_user(&self, user: User) -> io::Result<()>;
+    async fn delete_user(&self, username: &str) -> io::Result<bool>;
+
+    async fn list_images(&self) -> Vec<ImageMeta>;
+    async fn find_image_by_filename(&self, filename: &str) -> Option<ImageMeta>;
+    async fn find_image_by_id(&self, id: &str) -> Option<ImageMeta>;
+    async fn push_image(&self, image: ImageMeta) -> io::Result<()>;
+    async fn upsert_image(&self, image: ImageMeta) -> io::Result<()>;
+    async fn delete_image_by_filename(&self, filename: &str) -> io::Result<()>;
+    async fn delete_image_by_id(&self, id: &str) -> io::Result<Option<ImageMeta>>;
+
+    async fn get_settings(&self) -> SettingsJson;
+    async fn set_settings(&self, settings: SettingsJson) -> io::Result<()>;
+}
+
+/// backend implicit: păstrează exact comportamentul de dinainte (lock -> mutare
+/// -> clonare -> rescriere integrală a fișierului JSON)
+struct JsonStore {
+    cfg: Arc<Config>,
+    users: Mutex<UsersJson>,
+    images: Mutex<ImagesJson>,
+    settings: Mutex<SettingsJson>,
+}
+
+impl JsonStore {
+    fn new(cfg: Arc<Config>, users: UsersJson, images: ImagesJson, settings: SettingsJson) -> Self {
+        Self {
+            cfg,
+            users: Mutex::new(users),
+            images: Mutex::new(images),
+            settings: Mutex::new(settings),
+        }
+    }
+}
+
+#[async_trait]
+impl Store for JsonStore {
+    async fn list_users(&self) -> Vec<User> {
+        self.users.lock().users.clone()
+    }
+    async fn find_user(&self, username: &str) -> Option<User> {
+        self.users.lock().users.iter().find(|u| u.username == username).cloned()
+    }
+    async fn find_user_by_email(&self, email: &str) -> Option<User> {
+        let lower = email.trim().to_lowercase();
+        if lower.is_empty() {
+            return None;
+        }
+        self.users.lock().users.iter().find(|u| u.email.to_lowercase() == lower).cloned()
+    }
+    async fn upsert_user(&self, user: User) -> io::Result<()> {
+        let snapshot = {
+            let mut users = self.users.lock();
+            if let Some(existing) = users.users.iter_mut().find(|u| u.username == user.username) {
+                *existing = user;
+            } else {
+                users.users.push(user);
+            }
+            users.clone()
+        };
+        save_users(&self.cfg, &snapshot).await
+    }
+    async fn delete_user(&self, username: &str) -> io::Result<bool> {
+        let (found, snapshot) = {
+            let mut users = self.users.lock();
+            let before = users.users.len();
+            users.users.retain(|u| u.username != username);
+            (before != users.users.len(), users.clone())
+        };
+        save_users(&self.cfg, &snapshot).await?;
+        Ok(found)
+    }
+
+    async fn list_images(&self) -> Vec<ImageMeta> {
+        self.images.lock().images.clone()
+    }
+    async fn find_image_by_filename(&self, filename: &str) -> Option<ImageMeta> {
+        self.images.lock().images.iter().find(|i| i.filename == filename).cloned()
+    }
+    async fn find_image_by_id(&self, id: &str) -> Option<ImageMeta> {
+        self.images.lock().images.iter().find(|i| i.id == id).cloned()
+    }
+    async fn push_image(&self, image: ImageMeta) -> io::Result<()> {
+        // filename e unic (la fel ca în SqliteStore) — upsert, nu append necondiționat
+        self.upsert_image(image).await
+    }
+    async fn upsert_image(&self, image: ImageMeta) -> io::Result<()> {
+        let snapshot = {
+            let mut images = self.images.lock();
+            if let Some(existing) = images.images.iter_mut().find(|i| i.filename == image.filename) {
+                *existing = image;
+            } else {
+                images.images.push(image);
+            }
+            images.clone()
+        };
+        save_images(&self.cfg, &snapshot).await
+    }
+    async fn delete_image_by_filename(&self, filename: &str) -> io::Result<()> {
+        let snapshot = {
+            let mut images = self.images.lock();
+            images.images.retain(|i| i.filename != filename);
+            images.clone()
+        };
+        save_images(&self.cfg, &snapshot).await
+    }
+    async fn delete_image_by_id(&self, id: &str) -> io::Result<Option<ImageMeta>> {
+        let (removed, snapshot) = {
+            let mut images = self.images.lock();
+            let pos = images.images.iter().position(|i| i.id == id);
+            let removed = pos.map(|p| images.images.remove(p));
+            (removed, images.clone())
+        };
+        save_images(&self.cfg, &snapshot).await?;
+        Ok(removed)
+    }
+
+    async fn get_settings(&self) -> SettingsJson {
+        self.settings.lock().clone()
+    }
+    async fn set_settings(&self, settings: SettingsJson) -> io::Result<()> {
+        *self.settings.lock() = settings.clone();
+        save_settings(&self.cfg, &settings).await
+    }
+}
+
+/// backend opțional, ales prin STORAGE_BACKEND=sqlite sau DATABASE_URL (vezi build_store());
+/// face operații punctuale pe rânduri în loc să rescrie colecții întregi
+struct SqliteStore {
+    pool: SqlitePool,
+}
+
+impl SqliteStore {
+    async fn new(database_url: &str) -> Result<Self, sqlx::Error> {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(5)
+            .connect(database_url)
+            .await?;
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS users (
+                username TEXT PRIMARY KEY,
+                password_hash TEXT NOT NULL,
+                email TEXT NOT NULL,
+                created_at INTEGER NOT NULL,
+                role TEXT NOT NULL,
+                preferences TEXT NOT NULL,
+                totp_secret TEXT,
+                pending_totp_secret TEXT,
+                verified INTEGER NOT NULL
+            )",
+        )
+        .execute(&pool)
+        .await?;
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS images (
+                id TEXT PRIMARY KEY,
+                filename TEXT NOT NULL UNIQUE,
+                originalname TEXT NOT NULL,
+                size INTEGER NOT NULL,
+                url TEXT NOT NULL,
+                uploaded_at INTEGER NOT NULL,
+                owner TEXT,
+                sniffed_mime TEXT,
+                embed TEXT NOT NULL,
+                sensitive INTEGER NOT NULL,
+                content_warning TEXT,
+                alt_text TEXT,
+                visibility TEXT NOT NULL,
+                encrypted INTEGER NOT NULL,
+                enc_nonce TEXT
+            )",
+        )
+        .execute(&pool)
+        .await?;
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS settings (id INTEGER PRIMARY KEY CHECK (id = 0), register_blocked INTEGER NOT NULL)",
+        )
+        .execute(&pool)
+        .await?;
+        sqlx::query("INSERT OR IGNORE INTO settings (id, register_blocked) VALUES (0, 0)")
+            .execute(&pool)
+            .await?;
+        Ok(Self { pool })
+    }
+}
+
+fn sqlx_io_err(e: sqlx::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e)
+}
+
+fn user_from_row(row: &sqlx::sqlite::SqliteRow) -> User {
+    let preferences_json: String = row.get("preferences");
+    User {
+        username: row.get("username"),
+        password_hash: row.get("password_hash"),
+        email: row.get("email"),
+        created_at: row.get::<i64, _>("created_at") as u64,
+        role: row.get("role"),
+        preferences: serde_json::from_str(&preferences_json).unwrap_or_default(),
+        totp_secret: row.get("totp_secret"),
+        pending_totp_secret: row.get("pending_totp_secret"),
+        verified: row.get("verified"),
+    }
+}
+
+fn image_from_row(row: &sqlx::sqlite::SqliteRow) -> ImageMeta {
+    let embed_json: String = row.get("embed");
+    ImageMeta {
+        id: row.get("id"),
+        filename: row.get("filename"),
+        originalname: row.get("originalname"),
+        size: row.get::<i64, _>("size") as u64,
+        url: row.get("url"),
+        uploaded_at: row.get::<i64, _>("uploaded_at") as u64,
+        owner: row.get("owner"),
+        sniffed_mime: row.get("sniffed_mime"),
+        embed: serde_json::from_str(&embed_json).unwrap_or_default(),
+        sensitive: row.get("sensitive"),
+        content_warning: row.get("content_warning"),
+        alt_text: row.get("alt_text"),
+        visibility: row.get("visibility"),
+        encrypted: row.get("encrypted"),
+        enc_nonce: row.get("enc_nonce"),
+    }
+}
+
+#[async_trait]
+impl Store for SqliteStore {
+    async fn list_users(&self) -> Vec<User> {
+        sqlx::query("SELECT * FROM users")
+            .fetch_all(&self.pool)
+            .await
+            .map(|rows| rows.iter().map(user_from_row).collect())
+            .unwrap_or_default()
+    }
+    async fn find_user(&self, username: &str) -> Option<User> {
+        sqlx::query("SELECT * FROM users WHERE username = ?")
+            .bind(username)
+            .fetch_optional(&self.pool)
+            .await
+            .ok()
+            .flatten()
+            .as_ref()
+            .map(user_from_row)
+    }
+    async fn find_user_by_email(&self, email: &str) -> Option<User> {
+        let lower = email.trim().to_lowercase();
+        if lower.is_empty() {
+            return None;
+        }
+        sqlx::query("SELECT * FROM users WHERE lower(email) = ?")
+            .bind(lower)
+            .fetch_optional(&self.pool)
+            .await
+            .ok()
+            .flatten()
+            .as_ref()
+            .map(user_from_row)
+    }
+    async fn upsert_user(&self, user: User) -> io::Result<()> {
+        let preferences_json = serde_json::to_string(&user.preferences).unwrap_or_default();
+        sqlx::query(
+            "INSERT INTO users (username, password_hash, email, created_at, role, preferences, totp_secret, pending_totp_secret, verified)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+             ON CONFLICT(username) DO UPDATE SET
+                password_hash = excluded.password_hash,
+                email = excluded.email,
+                created_at = excluded.created_at,
+                role = excluded.role,
+                preferences = excluded.preferences,
+                totp_secret = excluded.totp_secret,
+                pending_totp_secret = excluded.pending_totp_secret,
+                verified = excluded.verified",
+        )
+        .bind(&user.username)
+        .bind(&user.password_hash)
+        .bind(&user.email)
+        .bind(user.created_at as i64)
+        .bind(&user.role)
+        .bind(preferences_json)
+        .bind(&user.totp_secret)
+        .bind(&user.pending_totp_secret)
+        .bind(user.verified)
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+        .map_err(sqlx_io_err)
+    }
+    async fn delete_user(&self, username: &str) -> io::Result<bool> {
+        let res = sqlx::query("DELETE FROM users WHERE username = ?")
+            .bind(username)
+            .execute(&self.pool)
+            .await
+            .map_err(sqlx_io_err)?;
+        Ok(res.rows_affected() > 0)
+    }
+
+    async fn list_images(&self) -> Vec<ImageMeta> {
+        sqlx::query("SELECT * FROM images")
+            .fetch_all(&self.pool)
+            .await
+            .map(|rows| rows.iter().map(image_from_row).collect())
+            .unwrap_or_default()
+    }
+    async fn find_image_by_filename(&self, filename: &str) -> Option<ImageMeta> {
+        sqlx::query("SELECT * FROM images WHERE filename = ?")
+            .bind(filename)
+            .fetch_optional(&self.pool)
+            .await
+            .ok()
+            .flatten()
+            .as_ref()
+            .map(image_from_row)
+    }
+    async fn find_image_by_id(&self, id: &str) -> Option<ImageMeta> {
+        sqlx::query("SELECT * FROM images WHERE id = ?")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await
+            .ok()
+            .flatten()
+            .as_ref()
+            .map(image_from_row)
+    }
+    async fn push_image(&self, image: ImageMeta) -> io::Result<()> {
+        self.upsert_image(image).await
+    }
+    async fn upsert_image(&self, image: ImageMeta) -> io::Result<()> {
+        let embed_json = serde_json::to_string(&image.embed).unwrap_or_default();
+        sqlx::query(
+            "INSERT INTO images (id, filename, originalname, size, url, uploaded_at, owner, sniffed_mime, embed, sensitive, content_warning, alt_text, visibility, encrypted, enc_nonce)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+             ON CONFLICT(filename) DO UPDATE SET
+                originalname = excluded.originalname,
+                size = excluded.size,
+                url = excluded.url,
+                uploaded_at = excluded.uploaded_at,
+                owner = excluded.owner,
+                sniffed_mime = excluded.sniffed_mime,
+                embed = excluded.embed,
+                sensitive = excluded.sensitive,
+                content_warning = excluded.content_warning,
+                alt_text = excluded.alt_text,
+                visibility = excluded.visibility,
+                encrypted = excluded.encrypted,
+                enc_nonce = excluded.enc_nonce",
+        )
+        .bind(&image.id)
+        .bind(&image.filename)
+        .bind(&image.originalname)
+        .bind(image.size as i64)
+        .bind(&image.url)
+        .bind(image.uploaded_at as i64)
+        .bind(&image.owner)
+        .bind(&image.sniffed_mime)
+        .bind(embed_json)
+        .bind(image.sensitive)
+        .bind(&image.content_warning)
+        .bind(&image.alt_text)
+        .bind(&image.visibility)
+        .bind(image.encrypted)
+        .bind(&image.enc_nonce)
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+        .map_err(sqlx_io_err)
+    }
+    async fn delete_image_by_filename(&self, filename: &str) -> io::Result<()> {
+        sqlx::query("DELETE FROM images WHERE filename = ?")
+            .bind(filename)
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+            .map_err(sqlx_io_err)
+    }
+    async fn delete_image_by_id(&self, id: &str) -> io::Result<Option<ImageMeta>> {
+        let existing = sqlx::query("SELECT * FROM images WHERE id = ?")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await
+            .ok()
+            .flatten()
+            .as_ref()
+            .map(image_from_row);
+        if existing.is_some() {
+            sqlx::query("DELETE FROM images WHERE id = ?")
+                .bind(id)
+                .execute(&self.pool)
+                .await
+                .map_err(sqlx_io_err)?;
+        }
+        Ok(existing)
+    }
+
+    async fn get_settings(&self) -> SettingsJson {
+        sqlx::query("SELECT register_blocked FROM settings WHERE id = 0")
+            .fetch_optional(&self.pool)
+            .await
+            .ok()
+            .flatten()
+            .map(|row| SettingsJson { registerBlocked: row.get("register_blocked") })
+            .unwrap_or_default()
+    }
+    async fn set_settings(&self, settings: SettingsJson) -> io::Result<()> {
+        sqlx::query(
+            "INSERT INTO settings (id, register_blocked) VALUES (0, ?)
+             ON CONFLICT(id) DO UPDATE SET register_blocked = excluded.register_blocked",
+        )
+        .bind(settings.registerBlocked)
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+        .map_err(sqlx_io_err)
+    }
+}
+
+/// alege backend-ul de persistență la pornire: STORAGE_BACKEND=sqlite sau un
+/// DATABASE_URL prezent comută pe SqliteStore, altfel rămânem pe JsonStore
+/// (mirrors vaultwarden's DbConnType::from_url)
+async fn build_store(
+    cfg: &Arc<Config>,
+    env_kv: &HashMap<String, String>,
+    users: UsersJson,
+    images: ImagesJson,
+    settings: SettingsJson,
+) -> Result<Arc<dyn Store>, Box<dyn std::error::Error>> {
+    let backend = env_kv.get("STORAGE_BACKEND").map(|s| s.to_ascii_lowercase());
+    let database_url = env_kv.get("DATABASE_URL").filter(|s| !s.is_empty()).cloned();
+
+    if backend.as_deref() == Some("sqlite") || database_url.is_some() {
+        let url = database_url.unwrap_or_else(|| {
+            format!("sqlite://{}/adedge.db?mode=rwc", cfg.data_dir.display())
+        });
+        let store = SqliteStore::new(&url).await?;
+        // la prima rulare (sau la migrarea de pe JsonStore) tabelul users e gol — băgăm
+        // rândurile deja încărcate din JSON (inclusiv admin-ul seedat de ensure_data_and_admin),
+        // altfel le lăsăm neatinse ca să nu suprascriem starea deja persistată în SQLite
+        if store.list_users().await.is_empty() {
+            for user in users.users {
+                store.upsert_user(user).await?;
+            }
+            for image in images.images {
+                store.upsert_image(image).await?;
+            }
+            store.set_settings(settings).await?;
+        }
+        Ok(Arc::new(store))
+    } else {
+        Ok(Arc::new(JsonStore::new(cfg.clone(), users, images, settings)))
+    }
 }
 
 // ========================= Utilitare =========================
@@ -256,6 +869,308 @@ fn is_image_mime(filename: &str) -> bool {
     guess.type_() == mime::IMAGE
 }
 
+// ========================= Content sniffing =========================
+// câți octeți citim din fluxul de upload înainte de prima scriere,
+// suficient pentru toate semnăturile de mai jos
+const SNIFF_BYTES: usize = 16;
+
+/// detectează tipul real al conținutului după primii octeți (magic bytes),
+/// independent de extensia/numele de fișier trimis de client
+fn sniff_mime(buf: &[u8]) -> Option<&'static str> {
+    if buf.len() >= 6 && (&buf[0..6] == b"GIF87a" || &buf[0..6] == b"GIF89a") {
+        return Some("image/gif");
+    }
+    if buf.len() >= 3 && buf[0] == 0xFF && buf[1] == 0xD8 && buf[2] == 0xFF {
+        return Some("image/jpeg");
+    }
+    if buf.len() >= 8 && buf[0..8] == [0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A] {
+        return Some("image/png");
+    }
+    if buf.len() >= 12 && &buf[0..4] == b"RIFF" && &buf[8..12] == b"WEBP" {
+        return Some("image/webp");
+    }
+    if buf.len() >= 12 && &buf[4..8] == b"ftyp" {
+        return Some("video/mp4");
+    }
+    if buf.len() >= 4 && buf[0..4] == [0x1A, 0x45, 0xDF, 0xA3] {
+        return Some("video/webm");
+    }
+    if buf.len() >= 4 && &buf[0..4] == b"%PDF" {
+        return Some("application/pdf");
+    }
+    None
+}
+
+fn guess_mime_for_upload(filename: &str, sniffed: Option<&str>) -> String {
+    if let Some(m) = sniffed {
+        return m.to_string();
+    }
+    mime_guess::from_path(filename)
+        .first_or_octet_stream()
+        .to_string()
+}
+
+// ========================= Criptare la rest (opțional) =========================
+// layout pe disc: blocuri de text clar de ENC_BLOCK_SIZE, fiecare criptat separat
+// (AES-256-GCM-SIV) cu propriul nonce, astfel încât un Range request să poată sări
+// direct la blocul dorit fără să decripteze tot fișierul
+const ENC_BLOCK_SIZE: usize = 64 * 1024;
+const ENC_TAG_LEN: usize = 16; // dimensiunea tag-ului GCM-SIV adăugat la fiecare bloc criptat
+const ENC_NONCE_BYTES: usize = 8; // nonce-ul per-fișier stocat în ImageMeta (restul de 4 octeți = indexul blocului)
+
+/// derivă cheia de criptare din SESSION_SECRET prin HKDF-SHA256, ca să nu reutilizăm
+/// direct secretul de sesiune ca cheie AES
+fn derive_encryption_key(secret: &str) -> Key<Aes256GcmSiv> {
+    let hk = Hkdf::<Sha256>::new(None, secret.as_bytes());
+    let mut okm = [0u8; 32];
+    hk.expand(b"adedge-at-rest-v1", &mut okm)
+        .expect("HKDF expand de 32 octeți nu poate eșua");
+    Key::<Aes256GcmSiv>::from(okm)
+}
+
+fn block_nonce(file_nonce: &[u8], block_index: u32) -> Nonce {
+    let mut n = [0u8; 12];
+    n[..ENC_NONCE_BYTES].copy_from_slice(file_nonce);
+    n[ENC_NONCE_BYTES..].copy_from_slice(&block_index.to_be_bytes());
+    Nonce::from(n)
+}
+
+fn encrypt_block(key: &Key<Aes256GcmSiv>, file_nonce: &[u8], block_index: u32, plaintext: &[u8]) -> Vec<u8> {
+    let cipher = Aes256GcmSiv::new(key);
+    cipher
+        .encrypt(&block_nonce(file_nonce, block_index), plaintext)
+        .expect("criptarea unui bloc nu poate eșua")
+}
+
+fn decrypt_block(key: &Key<Aes256GcmSiv>, file_nonce: &[u8], block_index: u32, ciphertext: &[u8]) -> io::Result<Vec<u8>> {
+    let cipher = Aes256GcmSiv::new(key);
+    cipher
+        .decrypt(&block_nonce(file_nonce, block_index), ciphertext)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "decriptare eșuată (fișier corupt sau cheie greșită)"))
+}
+
+/// acumulează octeții primiți din stream și scrie pe disc blocuri criptate de
+/// dimensiune fixă (ultimul bloc poate fi mai mic)
+struct EncryptingWriter {
+    key: Key<Aes256GcmSiv>,
+    file_nonce: [u8; ENC_NONCE_BYTES],
+    block_index: u32,
+    buf: Vec<u8>,
+}
+
+impl EncryptingWriter {
+    fn new(key: Key<Aes256GcmSiv>, file_nonce: [u8; ENC_NONCE_BYTES]) -> Self {
+        Self {
+            key,
+            file_nonce,
+            block_index: 0,
+            buf: Vec::with_capacity(ENC_BLOCK_SIZE),
+        }
+    }
+
+    async fn write_all(&mut self, file: &mut tfs::File, mut data: &[u8]) -> io::Result<()> {
+        while !data.is_empty() {
+            let space = ENC_BLOCK_SIZE - self.buf.len();
+            let take = space.min(data.len());
+            self.buf.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.buf.len() == ENC_BLOCK_SIZE {
+                self.flush_block(file).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn flush_block(&mut self, file: &mut tfs::File) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let ct = encrypt_block(&self.key, &self.file_nonce, self.block_index, &self.buf);
+        file.write_all(&ct).await?;
+        self.block_index += 1;
+        self.buf.clear();
+        Ok(())
+    }
+
+    /// criptează ultimul bloc parțial (dacă există) — de apelat o singură dată, la final
+    async fn finish(mut self, file: &mut tfs::File) -> io::Result<()> {
+        self.flush_block(file).await
+    }
+}
+
+/// scrie pe disc fie în clar, fie (dacă `enc` e Some) prin `EncryptingWriter`
+async fn write_upload_chunk(
+    file: &mut tfs::File,
+    enc: &mut Option<EncryptingWriter>,
+    data: &[u8],
+) -> io::Result<()> {
+    match enc {
+        Some(w) => w.write_all(file, data).await,
+        None => file.write_all(data).await,
+    }
+}
+
+/// citește și decriptează doar blocurile care acoperă [start, end] (inclusiv),
+/// pentru a servi Range requests fără a decripta tot fișierul
+async fn read_decrypted_range(
+    file_path: &FsPath,
+    key: &Key<Aes256GcmSiv>,
+    file_nonce: &[u8],
+    plain_len: u64,
+    start: u64,
+    end: u64,
+) -> io::Result<Vec<u8>> {
+    let stride = (ENC_BLOCK_SIZE + ENC_TAG_LEN) as u64;
+    let first_block = start / ENC_BLOCK_SIZE as u64;
+    let last_block = end / ENC_BLOCK_SIZE as u64;
+
+    let mut file = tfs::File::open(file_path).await?;
+    let mut out = Vec::with_capacity((end - start + 1) as usize);
+
+    for block_index in first_block..=last_block {
+        let block_plain_start = block_index * ENC_BLOCK_SIZE as u64;
+        let block_plain_len = (plain_len - block_plain_start).min(ENC_BLOCK_SIZE as u64) as usize;
+        let disk_offset = block_index * stride;
+        let ct_len = block_plain_len + ENC_TAG_LEN;
+
+        file.seek(SeekFrom::Start(disk_offset)).await?;
+        let mut ct = vec![0u8; ct_len];
+        file.read_exact(&mut ct).await?;
+
+        let plain = decrypt_block(key, file_nonce, block_index as u32, &ct)?;
+
+        let slice_start = if block_index == first_block {
+            (start - block_plain_start) as usize
+        } else {
+            0
+        };
+        let slice_end = if block_index == last_block {
+            (end - block_plain_start + 1) as usize
+        } else {
+            plain.len()
+        };
+        out.extend_from_slice(&plain[slice_start..slice_end]);
+    }
+
+    Ok(out)
+}
+
+// ========================= Transcodare / thumbnails la cerere =========================
+const PREVIEW_WIDTH: u32 = 640;
+// limită pt a evita decompression-bomb (nu redimensionăm niciodată peste asta)
+const MAX_TRANSCODE_WIDTH: u32 = 4096;
+
+#[derive(Debug, Deserialize)]
+struct ImageVariantQuery {
+    format: Option<String>,
+    w: Option<u32>,
+    size: Option<String>,
+    exp: Option<u64>,
+    sig: Option<String>,
+}
+
+/// GIF-urile (tratate ca animate) și fișierele video nu sunt retranscodate, se servesc ca atare
+fn skip_transcode(filename: &str, mime: &mime::Mime) -> bool {
+    let ext = FsPath::new(filename)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+    ext == "gif" || *mime == mime::IMAGE_GIF || mime.type_() == mime::VIDEO
+}
+
+fn variant_cache_path(upload_dir: &FsPath, filename: &str, width: u32, format: &str) -> PathBuf {
+    upload_dir.join(format!("{}.{}.{}", filename, width, format))
+}
+
+/// dacă `filename` arată ca un variant cache-uit (`{original}.{w}.{format}`, vezi `variant_cache_path`),
+/// întoarce numele fișierului original — altfel variantele n-ar avea `ImageMeta` și ar fi tratate
+/// ca publice de `check_private_access`, ocolind ACL-ul fișierului original pe care-l derivă
+fn variant_original_filename(filename: &str) -> Option<String> {
+    let rest = filename
+        .strip_suffix(".webp")
+        .or_else(|| filename.strip_suffix(".png"))?;
+    let (original, width) = rest.rsplit_once('.')?;
+    if !original.is_empty() && width.parse::<u32>().is_ok() {
+        Some(original.to_string())
+    } else {
+        None
+    }
+}
+
+/// caută `ImageMeta` pentru `filename`, iar dacă nu există (posibil un variant cache-uit),
+/// încearcă și fișierul original din care a fost derivat — ca ACL-ul să se aplice și variantelor
+async fn find_image_meta_for_access(state: &AppState, filename: &str) -> Option<ImageMeta> {
+    if let Some(meta) = state.store.find_image_by_filename(filename).await {
+        return Some(meta);
+    }
+    let original = variant_original_filename(filename)?;
+    state.store.find_image_by_filename(&original).await
+}
+
+fn variant_mime(format: &str) -> &'static str {
+    match format {
+        "webp" => "image/webp",
+        "png" => "image/png",
+        _ => "image/webp",
+    }
+}
+
+/// redimensionează/reencodează imaginea originală, cu cache pe disc lângă fișierul original
+async fn get_or_create_variant(
+    cfg: &Config,
+    filename: &str,
+    original_path: &PathBuf,
+    width: u32,
+    format: &str,
+) -> io::Result<PathBuf> {
+    let width = width.clamp(1, MAX_TRANSCODE_WIDTH);
+    let format = if format == "png" { "png" } else { "webp" };
+    let cache_path = variant_cache_path(&cfg.upload_dir, filename, width, format);
+    if tfs::metadata(&cache_path).await.is_ok() {
+        return Ok(cache_path);
+    }
+
+    let original_path = original_path.clone();
+    let cache_path_blocking = cache_path.clone();
+    let format_owned = format.to_string();
+    tokio::task::spawn_blocking(move || -> io::Result<()> {
+        use image::ImageDecoder as _; // pentru decoder.set_limits(...)
+
+        // limitează decodarea însăși (nu doar rezultatul), altfel un fișier mic care se
+        // decomprimă la dimensiuni uriașe e decodat integral în memorie înainte de clamp
+        let mut decoder = image::ImageReader::open(&original_path)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+            .with_guessed_format()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+            .into_decoder()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let mut limits = image::Limits::no_limits();
+        limits.max_image_width = Some(MAX_TRANSCODE_WIDTH);
+        limits.max_image_height = Some(MAX_TRANSCODE_WIDTH);
+        limits.max_alloc = Some(MAX_TRANSCODE_WIDTH as u64 * MAX_TRANSCODE_WIDTH as u64 * 4);
+        decoder
+            .set_limits(limits)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let img = image::DynamicImage::from_decoder(decoder)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let target_w = width.min(img.width().max(1));
+        let resized = img.resize(target_w, u32::MAX, image::imageops::FilterType::Lanczos3);
+        let out_format = if format_owned == "png" {
+            image::ImageFormat::Png
+        } else {
+            image::ImageFormat::WebP
+        };
+        resized
+            .save_with_format(&cache_path_blocking, out_format)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+    })
+    .await
+    .map_err(|e| io::Error::new(io::ErrorKind::Other, e))??;
+
+    Ok(cache_path)
+}
+
 fn join_url(origin: &str, path: &str) -> String {
     if origin.ends_with('/') {
         format!("{}{}", origin.trim_end_matches('/'), path)
@@ -433,13 +1348,10 @@ async fn ensure_data_and_admin(
             email: "admin@example.com".into(),
             created_at: now_ms(),
             role: "admin".into(),
-            preferences: Preferences {
-                background: BackgroundPref {
-                    kind: "color".into(),
-                    value: DEFAULT_BG_COLOR.into(),
-                },
-            },
-            images: vec![],
+            preferences: Preferences::default(),
+            totp_secret: None,
+            pending_totp_secret: None,
+            verified: true,
         };
         let uj = UsersJson { users: vec![admin] };
         tfs::write(&cfg.users_file, serde_json::to_vec_pretty(&uj)?).await?;
@@ -457,10 +1369,16 @@ async fn ensure_data_and_admin(
         tfs::write(&cfg.settings_file, serde_json::to_vec_pretty(&sj)?).await?;
     }
 
+    // invitations.json
+    if !cfg.invitations_file.exists() {
+        let vj = InvitationsJson::default();
+        tfs::write(&cfg.invitations_file, serde_json::to_vec_pretty(&vj)?).await?;
+    }
+
     Ok(first_admin_plain)
 }
 
-async fn load_all(cfg: &Config) -> io::Result<(UsersJson, ImagesJson, SettingsJson)> {
+async fn load_all(cfg: &Config) -> io::Result<(UsersJson, ImagesJson, SettingsJson, InvitationsJson)> {
     let uj: UsersJson = serde_json::from_slice(&tfs::read(&cfg.users_file).await.unwrap_or_default())
         .unwrap_or_default();
     let ij: ImagesJson =
@@ -469,7 +1387,10 @@ async fn load_all(cfg: &Config) -> io::Result<(UsersJson, ImagesJson, SettingsJs
     let sj: SettingsJson =
         serde_json::from_slice(&tfs::read(&cfg.settings_file).await.unwrap_or_default())
             .unwrap_or_default();
-    Ok((uj, ij, sj))
+    let vj: InvitationsJson =
+        serde_json::from_slice(&tfs::read(&cfg.invitations_file).await.unwrap_or_default())
+            .unwrap_or_default();
+    Ok((uj, ij, sj, vj))
 }
 
 async fn save_users(cfg: &Config, users: &UsersJson) -> io::Result<()> {
@@ -481,6 +1402,9 @@ async fn save_images(cfg: &Config, images: &ImagesJson) -> io::Result<()> {
 async fn save_settings(cfg: &Config, settings: &SettingsJson) -> io::Result<()> {
     tfs::write(&cfg.settings_file, serde_json::to_vec_pretty(settings)?).await
 }
+async fn save_invitations(cfg: &Config, invitations: &InvitationsJson) -> io::Result<()> {
+    tfs::write(&cfg.invitations_file, serde_json::to_vec_pretty(invitations)?).await
+}
 
 // ========================= Rate limit =========================
 fn allow_rate(state: &AppState, ip: &str) -> bool {
@@ -503,31 +1427,98 @@ fn allow_rate(state: &AppState, ip: &str) -> bool {
     }
 }
 
-// ========================= Session =========================
-fn sign_session(secret: &str, username: &str, ts: u64) -> String {
-    let payload = format!("{}.{}", username, ts);
-    let mac = hmac_sign(secret, &payload);
-    format!("{}.{}", payload, mac)
+// ========================= JWT (HS256) minimal, fără crate extern =========================
+// în aceeași notă ca base32_encode/decode de mai jos: evităm o dependență nouă pentru
+// un format de encodare; semnătura vine din hmac_sign_bytes, comparația din timing_equal.
+const B64URL_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+fn b64url_encode(data: &[u8]) -> String {
+    let mut out = String::new();
+    let mut bits: u32 = 0;
+    let mut bit_count: u32 = 0;
+    for &byte in data {
+        bits = (bits << 8) | byte as u32;
+        bit_count += 8;
+        while bit_count >= 6 {
+            bit_count -= 6;
+            out.push(B64URL_ALPHABET[((bits >> bit_count) & 0x3F) as usize] as char);
+        }
+    }
+    if bit_count > 0 {
+        out.push(B64URL_ALPHABET[((bits << (6 - bit_count)) & 0x3F) as usize] as char);
+    }
+    out
 }
 
-fn verify_session(secret: &str, cookie_val: &str) -> Option<String> {
-    let mut parts: Vec<&str> = cookie_val.split('.').collect();
-    if parts.len() < 3 {
+fn b64url_decode(s: &str) -> Option<Vec<u8>> {
+    let mut bits: u32 = 0;
+    let mut bit_count: u32 = 0;
+    let mut out = Vec::new();
+    for c in s.chars() {
+        let val = B64URL_ALPHABET.iter().position(|&b| b as char == c)? as u32;
+        bits = (bits << 6) | val;
+        bit_count += 6;
+        if bit_count >= 8 {
+            bit_count -= 8;
+            out.push(((bits >> bit_count) & 0xFF) as u8);
+        }
+    }
+    Some(out)
+}
+
+fn hmac_sign_bytes(secret: &str, payload: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC key");
+    mac.update(payload.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn jwt_encode(secret: &str, claims: &serde_json::Value) -> String {
+    let header_b64 = b64url_encode(br#"{"alg":"HS256","typ":"JWT"}"#);
+    let payload_b64 = b64url_encode(claims.to_string().as_bytes());
+    let signing_input = format!("{}.{}", header_b64, payload_b64);
+    let sig = hmac_sign_bytes(secret, &signing_input);
+    format!("{}.{}", signing_input, b64url_encode(&sig))
+}
+
+/// verifică semnătura și expirarea (`exp`, în secunde Unix) și întoarce payload-ul ca JSON
+fn jwt_decode(secret: &str, token: &str) -> Option<serde_json::Value> {
+    let mut parts = token.split('.');
+    let header_b64 = parts.next()?;
+    let payload_b64 = parts.next()?;
+    let sig_b64 = parts.next()?;
+    if parts.next().is_some() {
+        return None;
+    }
+    let signing_input = format!("{}.{}", header_b64, payload_b64);
+    let expected_sig = hmac_sign_bytes(secret, &signing_input);
+    let given_sig = b64url_decode(sig_b64)?;
+    if !timing_equal(&hex::encode(&expected_sig), &hex::encode(&given_sig)) {
         return None;
     }
-    let mac = parts.pop().unwrap();
-    let ts = parts.pop().unwrap();
-    let username = parts.join(".");
-    let expected = hmac_sign(secret, &format!("{}.{}", username, ts));
-    if !timing_equal(&expected, mac) {
+    let payload_bytes = b64url_decode(payload_b64)?;
+    let payload: serde_json::Value = serde_json::from_slice(&payload_bytes).ok()?;
+    let exp = payload.get("exp")?.as_u64()?;
+    if now_s_f64() as u64 > exp {
         return None;
     }
-    let ts_num = ts.parse::<u64>().ok()?;
-    let age = now_ms().saturating_sub(ts_num);
-    if age > SESSION_MAX_AGE_MS {
+    Some(payload)
+}
+
+// ========================= Session =========================
+fn sign_session(secret: &str, username: &str, role: &str) -> String {
+    let iat = now_s_f64() as u64;
+    let exp = iat + SESSION_MAX_AGE_MS / 1000;
+    jwt_encode(secret, &serde_json::json!({"sub": username, "role": role, "iat": iat, "exp": exp}))
+}
+
+fn verify_session(secret: &str, cookie_val: &str) -> Option<String> {
+    let claims = jwt_decode(secret, cookie_val)?;
+    // un JWT cu `scope` (ex. upload, vezi generate_sxcu) e un credential limitat, nu o sesiune
+    // completă — fără verificarea asta, un token de upload scurs ar da acces la tot contul
+    if claims.get("scope").is_some() {
         return None;
     }
-    Some(username)
+    claims.get("sub")?.as_str().map(|s| s.to_string())
 }
 
 fn check_auth(headers: &HeaderMap, state: &AppState) -> Option<String> {
@@ -538,25 +1529,187 @@ fn check_auth(headers: &HeaderMap, state: &AppState) -> Option<String> {
     }
 }
 
-// ========================= Helpers diverse =========================
-fn get_origin(headers: &HeaderMap, scheme: &str, host: &str) -> String {
-    let proto = headers
-        .get("x-forwarded-proto")
-        .and_then(|v| v.to_str().ok())
-        .unwrap_or(scheme);
-    let host_hdr = headers
-        .get("x-forwarded-host")
-        .and_then(|v| v.to_str().ok())
-        .unwrap_or(host);
-    format!("{}://{}", proto, host_hdr)
+// ========================= RBAC =========================
+// ierarhie de roluri: user < moderator < admin (derive(Ord) respectă ordinea declarată)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Role {
+    User,
+    Moderator,
+    Admin,
 }
 
-fn prefer_https_origin(origin: &str, headers: &HeaderMap, state: &AppState) -> String {
-    let req_proto = headers
-        .get("x-forwarded-proto")
-        .and_then(|v| v.to_str().ok())
-        .unwrap_or("http");
-
+impl Role {
+    fn parse(raw: &str) -> Role {
+        match raw {
+            "admin" => Role::Admin,
+            "moderator" => Role::Moderator,
+            _ => Role::User,
+        }
+    }
+}
+
+/// verifică sesiunea și cere ca rolul userului să fie >= `min`; altfel întoarce Response-ul de eroare gata de returnat.
+/// dacă ADMIN_TOKEN e configurat, pragul Admin mai cere și un header `Authorization: Bearer <ADMIN_TOKEN>`
+/// (similar cu `disable_admin_token` din vaultwarden, dar inversat: prezența tokenului activează gate-ul)
+async fn require_role(headers: &HeaderMap, state: &AppState, min: Role) -> Result<User, Response> {
+    let Some(username) = check_auth(headers, state) else {
+        return Err(json_error(StatusCode::UNAUTHORIZED, "Unauthorized"));
+    };
+    let Some(user) = state.store.find_user(&username).await else {
+        return Err(json_error(StatusCode::UNAUTHORIZED, "Unauthorized"));
+    };
+    if Role::parse(&user.role) < min {
+        return Err(json_error(StatusCode::FORBIDDEN, "Insufficient privileges"));
+    }
+    if min == Role::Admin {
+        if let Some(expected) = &state.cfg.admin_token {
+            let given = headers
+                .get(header::AUTHORIZATION)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.strip_prefix("Bearer "));
+            if !given.map(|g| timing_equal(g, expected)).unwrap_or(false) {
+                return Err(json_error(StatusCode::FORBIDDEN, "Admin token required"));
+            }
+        }
+    }
+    Ok(user)
+}
+
+// ========================= Signed (expiring) file access =========================
+// HMAC peste "filename|exp", derivat din SESSION_SECRET, pt. /i/:filename?exp=..&sig=..
+fn sign_file_access(secret: &str, filename: &str, exp: u64) -> String {
+    hmac_sign(secret, &format!("{}|{}", filename, exp))
+}
+
+fn verify_file_access(secret: &str, filename: &str, exp: u64, sig: &str) -> bool {
+    timing_equal(&sign_file_access(secret, filename, exp), sig)
+}
+
+/// Some(status) dacă accesul trebuie refuzat, None dacă e permis
+fn check_private_access(
+    state: &AppState,
+    filename: &str,
+    meta: Option<&ImageMeta>,
+    exp: Option<u64>,
+    sig: Option<&str>,
+) -> Option<StatusCode> {
+    let is_private = meta.map(|m| m.visibility == "private").unwrap_or(false);
+    if !is_private {
+        return None;
+    }
+    let (exp, sig) = match (exp, sig) {
+        (Some(e), Some(s)) => (e, s),
+        _ => return Some(StatusCode::FORBIDDEN),
+    };
+    if now_s_f64() as u64 > exp {
+        return Some(StatusCode::GONE);
+    }
+    if !verify_file_access(state.session_secret.as_str(), filename, exp, sig) {
+        return Some(StatusCode::FORBIDDEN);
+    }
+    None
+}
+
+// ========================= 2FA (TOTP, RFC 6238) =========================
+const TOTP_SECRET_BYTES: usize = 20;
+const TOTP_STEP_SECS: u64 = 30;
+const TOTP_SKEW_STEPS: i64 = 1; // acceptă pasul curent ± 1 (~30s desincronizare de ceas)
+
+const BASE32_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// base32 (RFC 4648) fără padding — formatul folosit de aplicațiile authenticator
+fn base32_encode(data: &[u8]) -> String {
+    let mut out = String::new();
+    let mut bits: u32 = 0;
+    let mut bit_count: u32 = 0;
+    for &byte in data {
+        bits = (bits << 8) | byte as u32;
+        bit_count += 8;
+        while bit_count >= 5 {
+            bit_count -= 5;
+            out.push(BASE32_ALPHABET[((bits >> bit_count) & 0x1F) as usize] as char);
+        }
+    }
+    if bit_count > 0 {
+        out.push(BASE32_ALPHABET[((bits << (5 - bit_count)) & 0x1F) as usize] as char);
+    }
+    out
+}
+
+fn base32_decode(s: &str) -> Option<Vec<u8>> {
+    let mut bits: u32 = 0;
+    let mut bit_count: u32 = 0;
+    let mut out = Vec::new();
+    for c in s.trim().to_ascii_uppercase().chars() {
+        if c == '=' {
+            continue;
+        }
+        let val = BASE32_ALPHABET.iter().position(|&b| b as char == c)? as u32;
+        bits = (bits << 5) | val;
+        bit_count += 5;
+        if bit_count >= 8 {
+            bit_count -= 8;
+            out.push(((bits >> bit_count) & 0xFF) as u8);
+        }
+    }
+    Some(out)
+}
+
+/// codul TOTP pe 6 cifre pt. un pas (counter) dat, per RFC 6238 / HOTP (RFC 4226)
+fn totp_code_at(secret: &[u8], counter: u64) -> String {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC key");
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    let offset = (hash[hash.len() - 1] & 0x0F) as usize;
+    let bin = ((hash[offset] as u32 & 0x7F) << 24)
+        | ((hash[offset + 1] as u32) << 16)
+        | ((hash[offset + 2] as u32) << 8)
+        | (hash[offset + 3] as u32);
+    format!("{:06}", bin % 1_000_000)
+}
+
+/// acceptă codul curent sau pe cei adiacenți (±1 pas) pentru toleranță la desincronizarea ceasului
+fn verify_totp_code(secret_b32: &str, code: &str) -> bool {
+    let Some(secret) = base32_decode(secret_b32) else {
+        return false;
+    };
+    let counter = (now_s_f64() as u64) / TOTP_STEP_SECS;
+    for delta in -TOTP_SKEW_STEPS..=TOTP_SKEW_STEPS {
+        let c = (counter as i64 + delta).max(0) as u64;
+        if timing_equal(&totp_code_at(&secret, c), code) {
+            return true;
+        }
+    }
+    false
+}
+
+fn totp_otpauth_uri(username: &str, secret_b32: &str) -> String {
+    format!(
+        "otpauth://totp/ADEdge:{}?secret={}&issuer=ADEdge",
+        urlencoding::encode(username),
+        secret_b32
+    )
+}
+
+// ========================= Helpers diverse =========================
+fn get_origin(headers: &HeaderMap, scheme: &str, host: &str) -> String {
+    let proto = headers
+        .get("x-forwarded-proto")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or(scheme);
+    let host_hdr = headers
+        .get("x-forwarded-host")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or(host);
+    format!("{}://{}", proto, host_hdr)
+}
+
+fn prefer_https_origin(origin: &str, headers: &HeaderMap, state: &AppState) -> String {
+    let req_proto = headers
+        .get("x-forwarded-proto")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("http");
+
     let https_possible = req_proto.eq_ignore_ascii_case("https")
         || (state.cfg.ssl_key_path.is_some() && state.cfg.ssl_cert_path.is_some());
 
@@ -574,24 +1727,11 @@ fn client_user(user: &User) -> serde_json::Value {
         "created_at": user.created_at,
         "role": user.role,
         "backgroundPreference": user.preferences.background,
+        "totp_enabled": user.totp_secret.is_some(),
+        "verified": user.verified,
     })
 }
 
-fn find_user<'a>(users: &'a UsersJson, uname: &str) -> Option<&'a User> {
-    users.users.iter().find(|u| u.username == uname)
-}
-fn find_user_mut<'a>(users: &'a mut UsersJson, uname: &str) -> Option<&'a mut User> {
-    users.users.iter_mut().find(|u| u.username == uname)
-}
-
-fn find_user_by_email<'a>(users: &'a UsersJson, email: &str) -> Option<&'a User> {
-    let lower = email.trim().to_lowercase();
-    if lower.is_empty() {
-        return None;
-    }
-    users.users.iter().find(|u| u.email.to_lowercase() == lower)
-}
-
 fn sanitize_filename(s: &str) -> String {
     let mut safe = s
         .chars()
@@ -608,6 +1748,8 @@ fn sanitize_filename(s: &str) -> String {
 struct LoginBody {
     username: String,
     password: String,
+    #[serde(default)]
+    code: Option<String>,
 }
 
 async fn api_login(
@@ -619,10 +1761,7 @@ async fn api_login(
         return json_error(StatusCode::BAD_REQUEST, "username and password required");
     }
 
-    let user_opt = {
-        let users = state.users.lock();
-        find_user(&users, &body.username).cloned()
-    };
+    let user_opt = state.store.find_user(&body.username).await;
     let Some(user) = user_opt else {
         return json_error(StatusCode::UNAUTHORIZED, "Invalid credentials");
     };
@@ -632,8 +1771,27 @@ async fn api_login(
         return json_error(StatusCode::UNAUTHORIZED, "Invalid credentials");
     }
 
-    let ts = now_ms();
-    let cookie_val = sign_session(state.session_secret.as_str(), &user.username, ts);
+    if !user.verified {
+        return json_error(
+            StatusCode::FORBIDDEN,
+            "Account not verified. Check your email for the verification link.",
+        );
+    }
+
+    if let Some(secret) = &user.totp_secret {
+        match &body.code {
+            None => {
+                return Json(serde_json::json!({"success": true, "require2fa": true})).into_response();
+            }
+            Some(code) => {
+                if !verify_totp_code(secret, code) {
+                    return json_error(StatusCode::UNAUTHORIZED, "Invalid 2FA code");
+                }
+            }
+        }
+    }
+
+    let cookie_val = sign_session(state.session_secret.as_str(), &user.username, &user.role);
     let secure = std::env::var("NODE_ENV")
         .ok()
         .unwrap_or_default()
@@ -657,11 +1815,10 @@ async fn api_me(State(state): State<AppState>, headers: HeaderMap) -> Response {
     let Some(username) = check_auth(&headers, &state) else {
         return json_error(StatusCode::UNAUTHORIZED, "Unauthorized");
     };
-    let users = state.users.lock();
-    let Some(user) = find_user(&users, &username) else {
+    let Some(user) = state.store.find_user(&username).await else {
         return json_error(StatusCode::NOT_FOUND, "User not found");
     };
-    Json(serde_json::json!({"success": true, "user": client_user(user)})).into_response()
+    Json(serde_json::json!({"success": true, "user": client_user(&user)})).into_response()
 }
 
 #[derive(Deserialize)]
@@ -689,15 +1846,11 @@ async fn api_settings(
             );
         };
 
-        let current_hash = {
-            let users = state.users.lock();
-            let Some(user) = find_user(&users, &username) else {
-                return json_error(StatusCode::NOT_FOUND, "User not found");
-            };
-            user.password_hash.clone()
+        let Some(user) = state.store.find_user(&username).await else {
+            return json_error(StatusCode::NOT_FOUND, "User not found");
         };
 
-        let ok = bcrypt::verify(cur, &current_hash).unwrap_or(false);
+        let ok = bcrypt::verify(cur, &user.password_hash).unwrap_or(false);
         if !ok {
             return json_error(StatusCode::UNAUTHORIZED, "Current password incorrect");
         }
@@ -708,15 +1861,9 @@ async fn api_settings(
             );
         }
 
-        let hashed = bcrypt::hash(newp, 10).unwrap();
-        let snapshot = {
-            let mut users = state.users.lock();
-            if let Some(user) = find_user_mut(&mut users, &username) {
-                user.password_hash = hashed;
-            }
-            users.clone()
-        };
-        let _ = save_users(&state.cfg, &snapshot).await;
+        let mut user = user;
+        user.password_hash = bcrypt::hash(newp, 10).unwrap();
+        let _ = state.store.upsert_user(user).await;
     }
 
     // schimbare upload token
@@ -728,14 +1875,10 @@ async fn api_settings(
             );
         };
 
-        let current_hash = {
-            let users = state.users.lock();
-            let Some(user) = find_user(&users, &username) else {
-                return json_error(StatusCode::NOT_FOUND, "User not found");
-            };
-            user.password_hash.clone()
+        let Some(user) = state.store.find_user(&username).await else {
+            return json_error(StatusCode::NOT_FOUND, "User not found");
         };
-        let ok = bcrypt::verify(cur, &current_hash).unwrap_or(false);
+        let ok = bcrypt::verify(cur, &user.password_hash).unwrap_or(false);
         if !ok {
             return json_error(
                 StatusCode::UNAUTHORIZED,
@@ -793,31 +1936,98 @@ async fn api_update_email(
         return json_error(StatusCode::BAD_REQUEST, "Invalid email format");
     }
 
-    let (conflict, snapshot) = {
-        let mut users = state.users.lock();
-        let conflict = users
-            .users
-            .iter()
-            .any(|u| u.username != username && u.email.eq_ignore_ascii_case(new_email));
-        if conflict {
-            (true, users.clone())
-        } else {
-            if let Some(u) = find_user_mut(&mut users, &username) {
-                u.email = new_email.to_string();
-            }
-            (false, users.clone())
-        }
-    };
-
+    let conflict = state
+        .store
+        .list_users()
+        .await
+        .iter()
+        .any(|u| u.username != username && u.email.eq_ignore_ascii_case(new_email));
     if conflict {
         return json_error(StatusCode::CONFLICT, "Email already in use");
     }
 
-    let _ = save_users(&state.cfg, &snapshot).await;
+    let Some(mut user) = state.store.find_user(&username).await else {
+        return json_error(StatusCode::NOT_FOUND, "User not found");
+    };
+    user.email = new_email.to_string();
+    let _ = state.store.upsert_user(user).await;
     Json(serde_json::json!({"success": true, "email": new_email, "message": "Email updated"}))
         .into_response()
 }
 
+async fn api_2fa_enable(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    let Some(username) = check_auth(&headers, &state) else {
+        return json_error(StatusCode::UNAUTHORIZED, "Unauthorized");
+    };
+
+    let mut secret_bytes = vec![0u8; TOTP_SECRET_BYTES];
+    rand::thread_rng().fill_bytes(&mut secret_bytes);
+    let secret_b32 = base32_encode(&secret_bytes);
+
+    let Some(mut user) = state.store.find_user(&username).await else {
+        return json_error(StatusCode::NOT_FOUND, "User not found");
+    };
+    // secretul e ținut în așteptare, nu activ — altfel login-ul ar cere imediat un cod și un
+    // user care apelează /enable și nu mai ajunge la /verify (ex. nu a scanat QR-ul) s-ar bloca afară
+    user.pending_totp_secret = Some(secret_b32.clone());
+    let _ = state.store.upsert_user(user).await;
+
+    Json(serde_json::json!({
+        "success": true,
+        "secret": secret_b32,
+        "otpauth_url": totp_otpauth_uri(&username, &secret_b32),
+    }))
+    .into_response()
+}
+
+#[derive(Deserialize)]
+struct TotpCodeBody {
+    code: String,
+}
+
+async fn api_2fa_verify(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(body): Json<TotpCodeBody>,
+) -> Response {
+    let Some(username) = check_auth(&headers, &state) else {
+        return json_error(StatusCode::UNAUTHORIZED, "Unauthorized");
+    };
+
+    let Some(mut user) = state.store.find_user(&username).await else {
+        return json_error(StatusCode::NOT_FOUND, "User not found");
+    };
+    let Some(pending) = user.pending_totp_secret.clone() else {
+        return json_error(StatusCode::BAD_REQUEST, "No 2FA enrollment in progress");
+    };
+
+    if !verify_totp_code(&pending, &body.code) {
+        return json_error(StatusCode::UNAUTHORIZED, "Invalid 2FA code");
+    }
+
+    // codul e valid -> activăm secretul abia acum
+    user.totp_secret = Some(pending);
+    user.pending_totp_secret = None;
+    let _ = state.store.upsert_user(user).await;
+
+    Json(serde_json::json!({"success": true})).into_response()
+}
+
+async fn api_2fa_disable(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    let Some(username) = check_auth(&headers, &state) else {
+        return json_error(StatusCode::UNAUTHORIZED, "Unauthorized");
+    };
+
+    let Some(mut user) = state.store.find_user(&username).await else {
+        return json_error(StatusCode::NOT_FOUND, "User not found");
+    };
+    user.totp_secret = None;
+    user.pending_totp_secret = None;
+    let _ = state.store.upsert_user(user).await;
+
+    Json(serde_json::json!({"success": true})).into_response()
+}
+
 async fn api_bg_templates(State(state): State<AppState>, headers: HeaderMap) -> Response {
     if check_auth(&headers, &state).is_none() {
         return json_error(StatusCode::UNAUTHORIZED, "Unauthorized");
@@ -837,6 +2047,13 @@ fn verify_upload_token(state: &AppState, candidate: &str) -> bool {
     if candidate.is_empty() {
         return false;
     }
+    // JWT scurt-trăit cu scope "upload" (emis de /api/generate-sxcu)
+    if let Some(claims) = jwt_decode(state.session_secret.as_str(), candidate) {
+        if claims.get("scope").and_then(|v| v.as_str()) == Some("upload") {
+            return true;
+        }
+    }
+    // token unic, permanent, setat manual din Settings (comportamentul istoric)
     if let Some(ref plain) = *state.initial_upload_token_plain.lock() {
         if candidate == plain {
             return true;
@@ -860,6 +2077,8 @@ async fn finalize_upload(
     size: u64,
     scheme: &str,
     host: &str,
+    sniffed_mime: Option<String>,
+    enc_nonce: Option<String>,
 ) -> Response {
     let origin = get_origin(headers, scheme, host);
     let url = join_url(&origin, &format!("/i/{}", urlencoding::encode(&filename)));
@@ -872,10 +2091,7 @@ async fn finalize_upload(
         .and_then(|v| v.to_str().ok())
         .unwrap_or("");
 
-    let owner_username = {
-        let users = state.users.lock();
-        find_user_by_email(&users, email_hdr).map(|u| u.username.clone())
-    };
+    let owner_username = state.store.find_user_by_email(email_hdr).await.map(|u| u.username);
 
     let meta = ImageMeta {
         id: id.clone(),
@@ -884,28 +2100,18 @@ async fn finalize_upload(
         size,
         url: url.clone(),
         uploaded_at: now_ms(),
-        owner: owner_username.clone(),
+        owner: owner_username,
+        sniffed_mime: Some(guess_mime_for_upload(&filename, sniffed_mime.as_deref())),
+        embed: EmbedMeta::default(),
+        sensitive: false,
+        content_warning: None,
+        alt_text: None,
+        visibility: default_visibility(),
+        encrypted: enc_nonce.is_some(),
+        enc_nonce,
     };
 
-    // global images (snapshot & save)
-    let img_snapshot = {
-        let mut images = state.images.lock();
-        images.images.push(meta.clone());
-        images.clone()
-    };
-    let _ = save_images(&state.cfg, &img_snapshot).await;
-
-    // atașare la user.images
-    if let Some(owner) = owner_username {
-        let users_snapshot = {
-            let mut users = state.users.lock();
-            if let Some(u) = find_user_mut(&mut users, &owner) {
-                u.images.push(meta);
-            }
-            users.clone()
-        };
-        let _ = save_users(&state.cfg, &users_snapshot).await;
-    }
+    let _ = state.store.push_image(meta).await;
 
     Json(serde_json::json!({"success": true, "url": url, "delete_url": join_url(&origin, &format!("/api/images/{}", id)) }))
         .into_response()
@@ -964,8 +2170,20 @@ async fn upload_handler(
         }
     };
 
+    let enc_nonce: Option<[u8; ENC_NONCE_BYTES]> = if state.cfg.encrypt_at_rest {
+        let mut n = [0u8; ENC_NONCE_BYTES];
+        rand::thread_rng().fill_bytes(&mut n);
+        Some(n)
+    } else {
+        None
+    };
+    let mut enc_writer = enc_nonce.map(|n| EncryptingWriter::new(derive_encryption_key(&state.session_secret), n));
+
     let mut size: u64 = 0;
     let mut stream = body.into_data_stream();
+    let mut sniff_buf: Vec<u8> = Vec::with_capacity(SNIFF_BYTES);
+    let mut sniffed: Option<&'static str> = None;
+    let mut checked = false;
     while let Some(chunk_res) = stream.next().await {
         let chunk = match chunk_res {
             Ok(c) => c,
@@ -979,12 +2197,55 @@ async fn upload_handler(
             let _ = tfs::remove_file(&filepath).await;
             return json_error(StatusCode::PAYLOAD_TOO_LARGE, "File too large");
         }
-        if let Err(_) = file.write_all(&chunk).await {
+
+        if !checked {
+            sniff_buf.extend_from_slice(&chunk);
+            if sniff_buf.len() < SNIFF_BYTES {
+                continue;
+            }
+            checked = true;
+            sniffed = sniff_mime(&sniff_buf);
+            // fără semnătură recunoscută nu înseamnă "neverificat" — cădem pe extensie
+            // și tot trecem prin allow-list, altfel un .html/.svg fără magic bytes trece liber
+            let effective_mime = guess_mime_for_upload(&filename_header, sniffed);
+            if !state.cfg.allowed_upload_mimes.iter().any(|m| m == &effective_mime) {
+                let _ = tfs::remove_file(&filepath).await;
+                return json_error(StatusCode::UNSUPPORTED_MEDIA_TYPE, "File type not allowed");
+            }
+            if write_upload_chunk(&mut file, &mut enc_writer, &sniff_buf).await.is_err() {
+                let _ = tfs::remove_file(&filepath).await;
+                return json_error(StatusCode::BAD_REQUEST, "Upload error");
+            }
+            continue;
+        }
+
+        if write_upload_chunk(&mut file, &mut enc_writer, &chunk).await.is_err() {
             let _ = tfs::remove_file(&filepath).await;
             return json_error(StatusCode::BAD_REQUEST, "Upload error");
         }
     }
 
+    // fluxul s-a terminat înainte să adunăm SNIFF_BYTES octeți
+    if !checked && !sniff_buf.is_empty() {
+        sniffed = sniff_mime(&sniff_buf);
+        let effective_mime = guess_mime_for_upload(&filename_header, sniffed);
+        if !state.cfg.allowed_upload_mimes.iter().any(|m| m == &effective_mime) {
+            let _ = tfs::remove_file(&filepath).await;
+            return json_error(StatusCode::UNSUPPORTED_MEDIA_TYPE, "File type not allowed");
+        }
+        if write_upload_chunk(&mut file, &mut enc_writer, &sniff_buf).await.is_err() {
+            let _ = tfs::remove_file(&filepath).await;
+            return json_error(StatusCode::BAD_REQUEST, "Upload error");
+        }
+    }
+
+    if let Some(w) = enc_writer {
+        if w.finish(&mut file).await.is_err() {
+            let _ = tfs::remove_file(&filepath).await;
+            return json_error(StatusCode::INTERNAL_SERVER_ERROR, "Upload error");
+        }
+    }
+
     let host = headers
         .get(header::HOST)
         .and_then(|v| v.to_str().ok())
@@ -1002,6 +2263,8 @@ async fn upload_handler(
         size,
         scheme,
         host,
+        sniffed.map(|m| m.to_string()),
+        enc_nonce.map(hex::encode),
     )
     .await
 }
@@ -1010,6 +2273,7 @@ async fn upload_handler(
 async fn image_view(
     State(state): State<AppState>,
     Path(filename): Path<String>,
+    Query(query): Query<ImageVariantQuery>,
     headers: HeaderMap,
 ) -> Response {
     let file_path = state.cfg.upload_dir.join(&filename);
@@ -1017,6 +2281,17 @@ async fn image_view(
         return json_error(StatusCode::NOT_FOUND, "Not found");
     }
 
+    let img_meta_for_access = find_image_meta_for_access(&state, &filename).await;
+    if let Some(status) = check_private_access(
+        &state,
+        &filename,
+        img_meta_for_access.as_ref(),
+        query.exp,
+        query.sig.as_deref(),
+    ) {
+        return (status, "").into_response();
+    }
+
     let host   = headers.get(header::HOST).and_then(|v| v.to_str().ok()).unwrap_or("");
     let origin_env_or_hdr = std::env::var("PUBLIC_ORIGIN")
         .unwrap_or_else(|_| get_origin(&headers, "http", host));
@@ -1024,11 +2299,40 @@ async fn image_view(
 
     let media_url = join_url(&origin_best, &format!("/i/{}", urlencoding::encode(&filename)));
     let page_url  = join_url(&origin_best, &format!("/i/{}/view", urlencoding::encode(&filename)));
-    let title     = filename.clone();
+
+    let img_meta = img_meta_for_access;
+    let embed = img_meta.as_ref().map(|m| m.embed.clone()).unwrap_or_default();
+    let is_sensitive = img_meta.as_ref().map(|m| m.sensitive).unwrap_or(false);
+    let content_warning = img_meta
+        .as_ref()
+        .and_then(|m| m.content_warning.clone())
+        .unwrap_or_else(|| "Sensitive content".to_string());
+    let alt_text = img_meta
+        .as_ref()
+        .and_then(|m| m.alt_text.clone())
+        .unwrap_or_else(|| "Shared file".to_string());
+    let title       = embed.title.clone().unwrap_or_else(|| filename.clone());
+    let description = embed.description.clone().unwrap_or_else(|| "uploaded on ADEdge".to_string());
 
     let mime     = mime_guess::from_path(&file_path).first_or_octet_stream();
     let is_video = mime.type_() == mime::VIDEO;
 
+    // pentru og:image preferăm un thumbnail webp mic (generat/cache-uit la cerere),
+    // imaginile mari nu mai trebuie descărcate integral doar pentru un card Discord/Twitter
+    let og_image_url = if !is_video && !skip_transcode(&filename, &mime) {
+        join_url(
+            &origin_best,
+            &format!("/i/{}?size=preview", urlencoding::encode(&filename)),
+        )
+    } else {
+        media_url.clone()
+    };
+    let og_image_url = if og_image_url.starts_with("http://") && origin_best.starts_with("https://") {
+        og_image_url.replacen("http://", "https://", 1)
+    } else {
+        og_image_url
+    };
+
     // normalizăm tipul pt OG (Discord preferă video/mp4)
     let ext = std::path::Path::new(&filename).extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
     let og_video_type = match ext.as_str() {
@@ -1047,31 +2351,65 @@ async fn image_view(
     };
     let is_https_media = media_url_https.starts_with("https://");
 
-    let media_tag = if is_video {
+    let inner_media_tag = if is_video {
         format!(
             "<video src=\"{}\" controls preload=\"metadata\" playsinline style=\"max-width:100%;max-height:80vh;border:6px solid rgba(255,255,255,0.06);box-shadow:0 6px 18px rgba(0,0,0,0.2);border-radius:8px\"></video>",
             escape_html(&media_url_https)
         )
     } else {
         format!(
-            "<img src=\"{}\" alt=\"Shared file\" style=\"max-width:100%;max-height:80vh;border:6px solid rgba(255,255,255,0.06);box-shadow:0 6px 18px rgba(0,0,0,0.2);border-radius:8px\"/>",
-            escape_html(&media_url)
+            "<img src=\"{}\" alt=\"{}\" style=\"max-width:100%;max-height:80vh;border:6px solid rgba(255,255,255,0.06);box-shadow:0 6px 18px rgba(0,0,0,0.2);border-radius:8px\"/>",
+            escape_html(&media_url),
+            escape_html(&alt_text)
         )
     };
 
+    // conținut marcat sensibil: blurat implicit, cu click-to-reveal
+    let media_tag = if is_sensitive {
+        format!(
+            "<div class=\"sensitive-wrap\"><div class=\"sensitive-blur\">{inner}</div><div class=\"sensitive-overlay\"><div class=\"sensitive-warning\">{warn}</div><button type=\"button\" onclick=\"this.closest('.sensitive-wrap').classList.add('revealed')\">Click to view</button></div></div>\
+             <style>.sensitive-wrap{{position:relative;display:inline-block}}.sensitive-wrap .sensitive-blur{{filter:blur(40px);transition:filter .2s}}.sensitive-wrap.revealed .sensitive-blur{{filter:none}}.sensitive-wrap .sensitive-overlay{{position:absolute;inset:0;display:flex;flex-direction:column;align-items:center;justify-content:center;gap:10px;background:rgba(0,0,0,0.35);color:#fff;text-align:center;padding:12px}}.sensitive-wrap.revealed .sensitive-overlay{{display:none}}</style>",
+            inner = inner_media_tag,
+            warn = escape_html(&content_warning)
+        )
+    } else {
+        inner_media_tag
+    };
+
+    // kindul efectiv: dacă embed-ul îl suprascrie explicit, îl folosim; altfel autodetectăm din fișier
+    let effective_kind = if embed.kind != EmbedKind::None {
+        embed.kind
+    } else if is_video {
+        EmbedKind::Video
+    } else {
+        EmbedKind::Image
+    };
+    let og_type = match effective_kind {
+        EmbedKind::Website => "website",
+        EmbedKind::Video => "video.other",
+        _ => "article",
+    };
+
     // --- OG pentru Discord: video ---
-    let og_block = if is_video {
+    let og_block = if is_sensitive {
+        // conținut sensibil: omitem og:image/og:video ca să nu se auto-extindă în unfurls
+        format!(
+            r#"<meta property="og:type" content="website">
+               <meta name="twitter:card" content="summary">"#,
+        )
+    } else if is_video {
         use std::fmt::Write as _;
         let mut meta = String::new();
 
         // Tipul OG de pagină + video URL + tip + dimensiuni
         let _ = write!(
             meta,
-            r#"<meta property="og:type" content="video.other">
+            r#"<meta property="og:type" content="{og_type}">
                <meta property="og:video" content="{u}">
                <meta property="og:video:type" content="{t}">
                <meta property="og:video:width" content="1280">
                <meta property="og:video:height" content="720">"#,
+            og_type = og_type,
             u = escape_html(&media_url_https),
             t = escape_html(og_video_type)
         );
@@ -1098,10 +2436,29 @@ async fn image_view(
         // --- OG pentru imagini ---
         format!(
             r#"<meta property="og:image" content="{}">
-               <meta property="og:type" content="article">
+               <meta property="og:image:alt" content="{}">
+               <meta property="og:type" content="{}">
                <meta name="twitter:card" content="summary_large_image">"#,
-            escape_html(&media_url)
+            escape_html(&og_image_url),
+            escape_html(&alt_text),
+            og_type
+        )
+    };
+
+    // theme-color + bloc oEmbed (Discord citește oEmbed pentru author_name/author_url)
+    let theme_color_tag = embed
+        .theme_color
+        .as_ref()
+        .map(|c| format!(r#"<meta name="theme-color" content="{}">"#, escape_html(c)))
+        .unwrap_or_default();
+    let oembed_tag = if embed.author_name.is_some() || embed.author_url.is_some() {
+        format!(
+            r#"<link rel="alternate" type="application/json+oembed" href="{}" title="{}">"#,
+            escape_html(&join_url(&origin_best, &format!("/api/oembed/{}", urlencoding::encode(&filename)))),
+            escape_html(&title)
         )
+    } else {
+        String::new()
     };
 
     let html = format!(
@@ -1110,9 +2467,11 @@ async fn image_view(
          <title>{}</title>
          <meta property=\"og:site_name\" content=\"ADEdge\">
          <meta property=\"og:title\" content=\"{}\">
-         <meta property=\"og:description\" content=\"uploaded on ADEdge\">
+         <meta property=\"og:description\" content=\"{}\">
          <meta property=\"og:url\" content=\"{}\">
          {}
+         {}
+         {}
          <style>
            html,body{{height:100%;margin:0}}
            body{{display:flex;align-items:center;justify-content:center;background:#0f9d58;color:#0a0a0a;font-family:Arial,Helvetica,sans-serif}}
@@ -1123,8 +2482,11 @@ async fn image_view(
          </body></html>",
         escape_html(&title),
         escape_html(&title),
+        escape_html(&description),
         escape_html(&page_url),
         og_block,
+        theme_color_tag,
+        oembed_tag,
         media_tag
     );
 
@@ -1137,6 +2499,7 @@ async fn image_view(
 async fn image_raw(
     State(state): State<AppState>,
     Path(filename): Path<String>,
+    Query(query): Query<ImageVariantQuery>,
     headers: HeaderMap
 ) -> Response {
     let file_path = state.cfg.upload_dir.join(&filename);
@@ -1144,7 +2507,115 @@ async fn image_raw(
         return json_error(StatusCode::NOT_FOUND, "Not found");
     }
 
-    let mime = mime_guess::from_path(&file_path).first_or_octet_stream();
+    let img_meta = state.store.find_image_by_filename(&filename).await;
+    // pt. un variant cache-uit (vezi variant_cache_path) ImageMeta-ul de mai sus e None; verificăm
+    // ACL-ul pe originalul din care a fost derivat, ca să nu ocolim vizibilitatea "private" prin el
+    let acl_meta = match &img_meta {
+        Some(m) => Some(m.clone()),
+        None => find_image_meta_for_access(&state, &filename).await,
+    };
+    if let Some(status) = check_private_access(
+        &state,
+        &filename,
+        acl_meta.as_ref(),
+        query.exp,
+        query.sig.as_deref(),
+    ) {
+        return (status, "").into_response();
+    }
+
+    let encrypted = img_meta.as_ref().map(|m| m.encrypted).unwrap_or(false);
+    let enc_nonce_hex = img_meta.as_ref().and_then(|m| m.enc_nonce.clone());
+    let plain_size = img_meta.as_ref().map(|m| m.size);
+    let sniffed_mime = img_meta.and_then(|m| m.sniffed_mime);
+    let base_mime = sniffed_mime.unwrap_or_else(|| {
+        mime_guess::from_path(&file_path)
+            .first_or_octet_stream()
+            .to_string()
+    });
+    let base_mime_parsed: mime::Mime = base_mime.parse().unwrap_or(mime::APPLICATION_OCTET_STREAM);
+
+    let wanted_width = match query.size.as_deref() {
+        Some("preview") => Some(PREVIEW_WIDTH),
+        _ => query.w,
+    };
+    let wanted_format = query.format.clone().unwrap_or_else(|| "webp".to_string());
+
+    // fișierele criptate sunt servite direct, decriptate pe loc — nu pot fi retranscodate
+    // fără a decripta întâi tot conținutul
+    let (file_path, mime_str) = if !encrypted
+        && base_mime_parsed.type_() == mime::IMAGE
+        && !skip_transcode(&filename, &base_mime_parsed)
+        && wanted_width.is_some()
+    {
+        match get_or_create_variant(&state.cfg, &filename, &file_path, wanted_width.unwrap(), &wanted_format).await {
+            Ok(variant_path) => (variant_path, variant_mime(&wanted_format).to_string()),
+            Err(_) => (file_path, base_mime),
+        }
+    } else {
+        (file_path, base_mime)
+    };
+
+    if encrypted {
+        let Some(file_nonce) = enc_nonce_hex.as_deref().and_then(|h| hex::decode(h).ok()) else {
+            return json_error(StatusCode::INTERNAL_SERVER_ERROR, "Missing encryption nonce");
+        };
+        let key = derive_encryption_key(&state.session_secret);
+        let total_len = plain_size.unwrap_or(0);
+
+        let range_hdr = headers.get(header::RANGE).and_then(|v| v.to_str().ok()).unwrap_or("");
+        let (start, end, is_partial) = if let Some(rest) = range_hdr.strip_prefix("bytes=") {
+            let mut start = 0u64;
+            let mut end = total_len.saturating_sub(1);
+            if let Some((s, e)) = rest.split_once('-') {
+                if !s.is_empty() { if let Ok(v) = s.parse() { start = v; } }
+                if !e.is_empty() { if let Ok(v) = e.parse() { end = v; } }
+                if end >= total_len { end = total_len.saturating_sub(1); }
+                if start > end { return (StatusCode::RANGE_NOT_SATISFIABLE, "").into_response(); }
+            }
+            (start, end, true)
+        } else {
+            (0, total_len.saturating_sub(1), false)
+        };
+
+        let bytes = if total_len == 0 {
+            Vec::new()
+        } else {
+            match read_decrypted_range(&file_path, &key, &file_nonce, total_len, start, end).await {
+                Ok(b) => b,
+                Err(_) => return json_error(StatusCode::INTERNAL_SERVER_ERROR, "Decryption failed"),
+            }
+        };
+
+        let content_len = bytes.len();
+        let mut resp = Response::new(Body::from(bytes));
+        if is_partial {
+            *resp.status_mut() = StatusCode::PARTIAL_CONTENT;
+            resp.headers_mut().insert(
+                header::CONTENT_RANGE,
+                HeaderValue::from_str(&format!("bytes {}-{}/{}", start, end, total_len)).unwrap(),
+            );
+        }
+        resp.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_str(&mime_str).unwrap_or(HeaderValue::from_static("application/octet-stream")),
+        );
+        resp.headers_mut().insert(
+            header::CONTENT_LENGTH,
+            HeaderValue::from_str(&content_len.to_string()).unwrap(),
+        );
+        resp.headers_mut().insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+        resp.headers_mut().insert(
+            header::CACHE_CONTROL,
+            HeaderValue::from_static("private, no-store"),
+        );
+        resp.headers_mut().insert(
+            header::CONTENT_DISPOSITION,
+            HeaderValue::from_static("inline"),
+        );
+        return resp;
+    }
+
     let mut file = match tfs::File::open(&file_path).await {
         Ok(f) => f,
         Err(_) => return json_error(StatusCode::NOT_FOUND, "Not found"),
@@ -1172,7 +2643,7 @@ async fn image_raw(
         *resp.status_mut() = StatusCode::PARTIAL_CONTENT;
         resp.headers_mut().insert(
             header::CONTENT_TYPE,
-            HeaderValue::from_str(mime.as_ref()).unwrap_or(HeaderValue::from_static("application/octet-stream")),
+            HeaderValue::from_str(&mime_str).unwrap_or(HeaderValue::from_static("application/octet-stream")),
         );
         resp.headers_mut().insert(
             header::CONTENT_RANGE,
@@ -1199,7 +2670,7 @@ async fn image_raw(
     let mut resp = Response::new(Body::from_stream(stream));
     resp.headers_mut().insert(
         header::CONTENT_TYPE,
-        HeaderValue::from_str(mime.as_ref()).unwrap_or(HeaderValue::from_static("application/octet-stream")),
+        HeaderValue::from_str(&mime_str).unwrap_or(HeaderValue::from_static("application/octet-stream")),
     );
     resp.headers_mut().insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
     resp.headers_mut().insert(
@@ -1220,18 +2691,44 @@ async fn image_raw(
 async fn image_head(
     State(state): State<AppState>,
     Path(filename): Path<String>,
+    Query(query): Query<ImageVariantQuery>,
 ) -> Response {
     let file_path = state.cfg.upload_dir.join(&filename);
     if !file_path.starts_with(&state.cfg.upload_dir) || !file_path.exists() {
         return (StatusCode::NOT_FOUND, "").into_response();
     }
-    let mime = mime_guess::from_path(&file_path).first_or_octet_stream();
-    let len  = fs::metadata(&file_path).map(|m| m.len()).unwrap_or(0);
+    let img_meta = state.store.find_image_by_filename(&filename).await;
+    let acl_meta = match &img_meta {
+        Some(m) => Some(m.clone()),
+        None => find_image_meta_for_access(&state, &filename).await,
+    };
+    if let Some(status) = check_private_access(
+        &state,
+        &filename,
+        acl_meta.as_ref(),
+        query.exp,
+        query.sig.as_deref(),
+    ) {
+        return (status, "").into_response();
+    }
+    let encrypted = img_meta.as_ref().map(|m| m.encrypted).unwrap_or(false);
+    let plain_size = img_meta.as_ref().map(|m| m.size);
+    let sniffed_mime = img_meta.and_then(|m| m.sniffed_mime);
+    let mime_str = sniffed_mime.unwrap_or_else(|| {
+        mime_guess::from_path(&file_path)
+            .first_or_octet_stream()
+            .to_string()
+    });
+    let len = if encrypted {
+        plain_size.unwrap_or(0)
+    } else {
+        fs::metadata(&file_path).map(|m| m.len()).unwrap_or(0)
+    };
 
     let mut resp = Response::new(Body::empty());
     resp.headers_mut().insert(
         header::CONTENT_TYPE,
-        HeaderValue::from_str(mime.as_ref()).unwrap_or(HeaderValue::from_static("application/octet-stream")),
+        HeaderValue::from_str(&mime_str).unwrap_or(HeaderValue::from_static("application/octet-stream")),
     );
     resp.headers_mut().insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
     resp.headers_mut().insert(
@@ -1250,11 +2747,14 @@ async fn api_images_list(State(state): State<AppState>, headers: HeaderMap) -> R
     let Some(username) = check_auth(&headers, &state) else {
         return json_error(StatusCode::UNAUTHORIZED, "Unauthorized");
     };
-    let users = state.users.lock();
-    let Some(u) = find_user(&users, &username) else {
-        return json_error(StatusCode::NOT_FOUND, "User not found");
-    };
-    Json(serde_json::json!({"success": true, "images": u.images})).into_response()
+    let images: Vec<ImageMeta> = state
+        .store
+        .list_images()
+        .await
+        .into_iter()
+        .filter(|img| img.owner.as_deref() == Some(username.as_str()))
+        .collect();
+    Json(serde_json::json!({"success": true, "images": images})).into_response()
 }
 
 #[derive(Deserialize)]
@@ -1267,7 +2767,7 @@ async fn api_images_delete_by_filename(
     headers: HeaderMap,
     Json(body): Json<DelByFilename>,
 ) -> Response {
-    let Some(username) = check_auth(&headers, &state) else {
+    let Some(_username) = check_auth(&headers, &state) else {
         return json_error(StatusCode::UNAUTHORIZED, "Unauthorized");
     };
     if body.filename.is_empty() {
@@ -1286,22 +2786,7 @@ async fn api_images_delete_by_filename(
     }
 
     // 2. curăță din images.json
-    let img_snapshot = {
-        let mut images = state.images.lock();
-        images.images.retain(|img| img.filename != safe);
-        images.clone()
-    };
-    let _ = save_images(&state.cfg, &img_snapshot).await;
-
-    // 3. curăță din users.json
-    let users_snapshot = {
-        let mut users = state.users.lock();
-        for u in users.users.iter_mut() {
-            u.images.retain(|m| m.filename != safe);
-        }
-        users.clone()
-    };
-    let _ = save_users(&state.cfg, &users_snapshot).await;
+    let _ = state.store.delete_image_by_filename(&safe).await;
 
     Json(serde_json::json!({"success": true})).into_response()
 }
@@ -1315,38 +2800,236 @@ async fn api_images_delete_by_id(
         return json_error(StatusCode::UNAUTHORIZED, "Unauthorized");
     };
 
-    // scoatem din user + salvăm users.json
-    let (maybe_item, users_snapshot) = {
-        let mut users = state.users.lock();
-        let Some(u) = find_user_mut(&mut users, &username) else {
-            return json_error(StatusCode::NOT_FOUND, "User not found");
-        };
-        let pos = u.images.iter().position(|i| i.id == id);
-        let item = pos.map(|p| u.images.remove(p));
-        (item, users.clone())
-    };
-
-    let Some(item) = maybe_item else {
+    let Some(item) = state.store.find_image_by_id(&id).await else {
         return json_error(StatusCode::NOT_FOUND, "Not found or not owned");
     };
-
-    let _ = save_users(&state.cfg, &users_snapshot).await;
+    if item.owner.as_deref() != Some(username.as_str()) {
+        return json_error(StatusCode::NOT_FOUND, "Not found or not owned");
+    }
 
     // șterge fișier
     let path = state.cfg.upload_dir.join(&item.filename);
     let _ = tfs::remove_file(&path).await;
 
-    // scoate din global images
-    let img_snapshot = {
-        let mut images = state.images.lock();
-        images.images.retain(|m| m.id != item.id);
-        images.clone()
+    let _ = state.store.delete_image_by_id(&item.id).await;
+
+    Json(serde_json::json!({"success": true})).into_response()
+}
+
+#[derive(Deserialize)]
+struct EmbedUpdateBody {
+    filename: String,
+    kind: Option<String>,
+    title: Option<String>,
+    description: Option<String>,
+    author_name: Option<String>,
+    author_url: Option<String>,
+    theme_color: Option<String>,
+}
+
+fn apply_embed_update(embed: &mut EmbedMeta, body: &EmbedUpdateBody) {
+    if let Some(k) = &body.kind {
+        embed.kind = parse_embed_kind(k);
+    }
+    if let Some(t) = &body.title {
+        embed.title = Some(t.clone());
+    }
+    if let Some(d) = &body.description {
+        embed.description = Some(d.clone());
+    }
+    if let Some(a) = &body.author_name {
+        embed.author_name = Some(a.clone());
+    }
+    if let Some(a) = &body.author_url {
+        embed.author_url = Some(a.clone());
+    }
+    if let Some(c) = &body.theme_color {
+        embed.theme_color = Some(c.clone());
+    }
+}
+
+async fn api_images_update_embed(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(body): Json<EmbedUpdateBody>,
+) -> Response {
+    let Some(username) = check_auth(&headers, &state) else {
+        return json_error(StatusCode::UNAUTHORIZED, "Unauthorized");
+    };
+    if body.filename.is_empty() {
+        return json_error(StatusCode::BAD_REQUEST, "Missing filename");
+    }
+
+    // doar proprietarul poate modifica embed-ul imaginii
+    let Some(mut img) = state.store.find_image_by_filename(&body.filename).await else {
+        return json_error(StatusCode::NOT_FOUND, "Image not found or not owned");
     };
-    let _ = save_images(&state.cfg, &img_snapshot).await;
+    if img.owner.as_deref() != Some(username.as_str()) {
+        return json_error(StatusCode::NOT_FOUND, "Image not found or not owned");
+    }
+    apply_embed_update(&mut img.embed, &body);
+    let _ = state.store.upsert_image(img).await;
 
     Json(serde_json::json!({"success": true})).into_response()
 }
 
+async fn api_oembed(State(state): State<AppState>, Path(filename): Path<String>) -> Response {
+    let file_path = state.cfg.upload_dir.join(&filename);
+    if !file_path.starts_with(&state.cfg.upload_dir) || !file_path.exists() {
+        return json_error(StatusCode::NOT_FOUND, "Not found");
+    }
+    let embed = state
+        .store
+        .find_image_by_filename(&filename)
+        .await
+        .map(|m| m.embed)
+        .unwrap_or_default();
+    let mime = mime_guess::from_path(&file_path).first_or_octet_stream();
+    let is_video = mime.type_() == mime::VIDEO;
+    let title = embed.title.clone().unwrap_or_else(|| filename.clone());
+
+    Json(serde_json::json!({
+        "version": "1.0",
+        "type": if is_video { "video" } else { "photo" },
+        "provider_name": "ADEdge",
+        "title": title,
+        "author_name": embed.author_name,
+        "author_url": embed.author_url,
+    }))
+    .into_response()
+}
+
+#[derive(Deserialize)]
+struct SensitiveUpdateBody {
+    filename: String,
+    sensitive: Option<bool>,
+    content_warning: Option<String>,
+    alt_text: Option<String>,
+}
+
+fn apply_sensitive_update(img: &mut ImageMeta, body: &SensitiveUpdateBody) {
+    if let Some(s) = body.sensitive {
+        img.sensitive = s;
+    }
+    if let Some(w) = &body.content_warning {
+        img.content_warning = Some(w.clone());
+    }
+    if let Some(a) = &body.alt_text {
+        img.alt_text = Some(a.clone());
+    }
+}
+
+async fn api_images_update_sensitive(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(body): Json<SensitiveUpdateBody>,
+) -> Response {
+    let Some(username) = check_auth(&headers, &state) else {
+        return json_error(StatusCode::UNAUTHORIZED, "Unauthorized");
+    };
+    if body.filename.is_empty() {
+        return json_error(StatusCode::BAD_REQUEST, "Missing filename");
+    }
+
+    // doar proprietarul poate marca imaginea ca fiind sensibilă
+    let Some(mut img) = state.store.find_image_by_filename(&body.filename).await else {
+        return json_error(StatusCode::NOT_FOUND, "Image not found or not owned");
+    };
+    if img.owner.as_deref() != Some(username.as_str()) {
+        return json_error(StatusCode::NOT_FOUND, "Image not found or not owned");
+    }
+    apply_sensitive_update(&mut img, &body);
+    let _ = state.store.upsert_image(img).await;
+
+    Json(serde_json::json!({"success": true})).into_response()
+}
+
+#[derive(Deserialize)]
+struct VisibilityUpdateBody {
+    filename: String,
+    visibility: String,
+}
+
+fn apply_visibility_update(img: &mut ImageMeta, body: &VisibilityUpdateBody) {
+    img.visibility = body.visibility.clone();
+}
+
+async fn api_images_update_visibility(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(body): Json<VisibilityUpdateBody>,
+) -> Response {
+    let Some(username) = check_auth(&headers, &state) else {
+        return json_error(StatusCode::UNAUTHORIZED, "Unauthorized");
+    };
+    if body.filename.is_empty() {
+        return json_error(StatusCode::BAD_REQUEST, "Missing filename");
+    }
+    if body.visibility != "public" && body.visibility != "private" {
+        return json_error(StatusCode::BAD_REQUEST, "visibility must be \"public\" or \"private\"");
+    }
+
+    // doar proprietarul poate schimba vizibilitatea
+    let Some(mut img) = state.store.find_image_by_filename(&body.filename).await else {
+        return json_error(StatusCode::NOT_FOUND, "Image not found or not owned");
+    };
+    if img.owner.as_deref() != Some(username.as_str()) {
+        return json_error(StatusCode::NOT_FOUND, "Image not found or not owned");
+    }
+    apply_visibility_update(&mut img, &body);
+    let _ = state.store.upsert_image(img).await;
+
+    Json(serde_json::json!({"success": true})).into_response()
+}
+
+const DEFAULT_SIGNED_URL_TTL_SECS: u64 = 300;
+const MAX_SIGNED_URL_TTL_SECS: u64 = 7 * 24 * 3600;
+
+#[derive(Deserialize)]
+struct SignUrlBody {
+    filename: String,
+    ttl_secs: Option<u64>,
+}
+
+async fn api_images_sign(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(body): Json<SignUrlBody>,
+) -> Response {
+    let Some(username) = check_auth(&headers, &state) else {
+        return json_error(StatusCode::UNAUTHORIZED, "Unauthorized");
+    };
+    if body.filename.is_empty() {
+        return json_error(StatusCode::BAD_REQUEST, "Missing filename");
+    }
+
+    // doar proprietarul poate genera un link semnat pentru fișierul lui
+    let owns = state
+        .store
+        .find_image_by_filename(&body.filename)
+        .await
+        .map(|i| i.owner.as_deref() == Some(username.as_str()))
+        .unwrap_or(false);
+    if !owns {
+        return json_error(StatusCode::NOT_FOUND, "Image not found or not owned");
+    }
+
+    let ttl = body
+        .ttl_secs
+        .unwrap_or(DEFAULT_SIGNED_URL_TTL_SECS)
+        .min(MAX_SIGNED_URL_TTL_SECS);
+    let exp = now_s_f64() as u64 + ttl;
+    let sig = sign_file_access(state.session_secret.as_str(), &body.filename, exp);
+
+    let url = format!(
+        "/i/{}?exp={}&sig={}",
+        urlencoding::encode(&body.filename),
+        exp,
+        sig
+    );
+    Json(serde_json::json!({"success": true, "url": url, "exp": exp})).into_response()
+}
+
 // ========================= Upload cu sesiune (dashboard) =========================
 async fn api_upload_dashboard(
     State(state): State<AppState>,
@@ -1367,19 +3050,71 @@ async fn api_upload_dashboard(
                 Ok(f) => f,
                 Err(_) => return json_error(StatusCode::BAD_REQUEST, "Upload error"),
             };
+            let enc_nonce: Option<[u8; ENC_NONCE_BYTES]> = if state.cfg.encrypt_at_rest {
+                let mut n = [0u8; ENC_NONCE_BYTES];
+                rand::thread_rng().fill_bytes(&mut n);
+                Some(n)
+            } else {
+                None
+            };
+            let mut enc_writer = enc_nonce.map(|n| EncryptingWriter::new(derive_encryption_key(&state.session_secret), n));
+
             let mut size: u64 = 0;
             let mut stream = field;
+            let mut sniff_buf: Vec<u8> = Vec::with_capacity(SNIFF_BYTES);
+            let mut sniffed: Option<&'static str> = None;
+            let mut checked = false;
             while let Some(chunk) = stream.chunk().await.unwrap() {
                 size += chunk.len() as u64;
                 if size > state.cfg.max_upload_bytes {
                     let _ = tfs::remove_file(&filepath).await;
                     return json_error(StatusCode::PAYLOAD_TOO_LARGE, "File too large");
                 }
-                if let Err(_) = file.write_all(&chunk).await {
+
+                if !checked {
+                    sniff_buf.extend_from_slice(&chunk);
+                    if sniff_buf.len() < SNIFF_BYTES {
+                        continue;
+                    }
+                    checked = true;
+                    sniffed = sniff_mime(&sniff_buf);
+                    // fără semnătură recunoscută nu înseamnă "neverificat" — cădem pe extensie
+                    // și tot trecem prin allow-list, altfel un .html/.svg fără magic bytes trece liber
+                    let effective_mime = guess_mime_for_upload(&filename_raw, sniffed);
+                    if !state.cfg.allowed_upload_mimes.iter().any(|m| m == &effective_mime) {
+                        let _ = tfs::remove_file(&filepath).await;
+                        return json_error(StatusCode::UNSUPPORTED_MEDIA_TYPE, "File type not allowed");
+                    }
+                    if write_upload_chunk(&mut file, &mut enc_writer, &sniff_buf).await.is_err() {
+                        let _ = tfs::remove_file(&filepath).await;
+                        return json_error(StatusCode::BAD_REQUEST, "Upload error");
+                    }
+                    continue;
+                }
+
+                if write_upload_chunk(&mut file, &mut enc_writer, &chunk).await.is_err() {
                     let _ = tfs::remove_file(&filepath).await;
                     return json_error(StatusCode::BAD_REQUEST, "Upload error");
                 }
             }
+            if !checked && !sniff_buf.is_empty() {
+                sniffed = sniff_mime(&sniff_buf);
+                let effective_mime = guess_mime_for_upload(&filename_raw, sniffed);
+                if !state.cfg.allowed_upload_mimes.iter().any(|m| m == &effective_mime) {
+                    let _ = tfs::remove_file(&filepath).await;
+                    return json_error(StatusCode::UNSUPPORTED_MEDIA_TYPE, "File type not allowed");
+                }
+                if write_upload_chunk(&mut file, &mut enc_writer, &sniff_buf).await.is_err() {
+                    let _ = tfs::remove_file(&filepath).await;
+                    return json_error(StatusCode::BAD_REQUEST, "Upload error");
+                }
+            }
+            if let Some(w) = enc_writer {
+                if w.finish(&mut file).await.is_err() {
+                    let _ = tfs::remove_file(&filepath).await;
+                    return json_error(StatusCode::INTERNAL_SERVER_ERROR, "Upload error");
+                }
+            }
 
             let host = headers.get(header::HOST).and_then(|v| v.to_str().ok()).unwrap_or("");
             // alege schema corectă pentru dashboard
@@ -1396,42 +3131,86 @@ async fn api_upload_dashboard(
                 url: url.clone(),
                 uploaded_at: now_ms(),
                 owner: Some(username.clone()),
+                sniffed_mime: Some(guess_mime_for_upload(&final_name, sniffed)),
+                embed: EmbedMeta::default(),
+                sensitive: false,
+                content_warning: None,
+                alt_text: None,
+                visibility: default_visibility(),
+                encrypted: enc_nonce.is_some(),
+                enc_nonce: enc_nonce.map(hex::encode),
             };
 
-            // images.json
-            let img_snapshot = {
-                let mut images = state.images.lock();
-                images.images.push(meta.clone());
-                images.clone()
-            };
-            let _ = save_images(&state.cfg, &img_snapshot).await;
+            let _ = state.store.push_image(meta).await;
+
+            return Json(serde_json::json!({"success": true, "url": url})).into_response();
+        }
+    }
+    json_error(StatusCode::BAD_REQUEST, "No file")
+}
+
+// ========================= Email (SMTP) =========================
+async fn send_mail(cfg: &Config, to: &str, subject: &str, body: &str) -> io::Result<()> {
+    let from = cfg
+        .smtp_from
+        .as_deref()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "SMTP not configured"))?;
+    let host = cfg
+        .smtp_host
+        .as_deref()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "SMTP not configured"))?;
+
+    let from_mbox: Mailbox = from
+        .parse()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid smtp_from: {}", e)))?;
+    let to_mbox: Mailbox = to
+        .parse()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid recipient: {}", e)))?;
+
+    let email = Message::builder()
+        .from(from_mbox)
+        .to(to_mbox)
+        .subject(subject)
+        .body(body.to_string())
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("mail build error: {}", e)))?;
+
+    let mut builder = AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("smtp relay error: {}", e)))?;
+    if let Some(port) = cfg.smtp_port {
+        builder = builder.port(port);
+    }
+    if let (Some(user), Some(pass)) = (&cfg.smtp_user, &cfg.smtp_pass) {
+        builder = builder.credentials(Credentials::new(user.clone(), pass.clone()));
+    }
+    let mailer = builder.build();
+
+    mailer
+        .send(email)
+        .await
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("smtp send error: {}", e)))?;
+    Ok(())
+}
+
+// ========================= Invitații + verificare email =========================
+const INVITE_TTL_SECS: u64 = 7 * 24 * 3600;
+const EMAIL_VERIFY_TTL_SECS: u64 = 24 * 3600;
 
-            // users.json
-            let users_snapshot = {
-                let mut users = state.users.lock();
-                if let Some(u) = find_user_mut(&mut users, &username) {
-                    u.images.push(meta);
-                }
-                users.clone()
-            };
-            let _ = save_users(&state.cfg, &users_snapshot).await;
+/// HMAC peste "verify|username|exp", derivat din SESSION_SECRET, pt. link-urile de verificare email
+fn sign_email_verification(secret: &str, username: &str, exp: u64) -> String {
+    hmac_sign(secret, &format!("verify|{}|{}", username, exp))
+}
 
-            return Json(serde_json::json!({"success": true, "url": url})).into_response();
-        }
-    }
-    json_error(StatusCode::BAD_REQUEST, "No file")
+fn verify_email_verification(secret: &str, username: &str, exp: u64, sig: &str) -> bool {
+    timing_equal(&sign_email_verification(secret, username, exp), sig)
 }
 
 // ========================= Admin =========================
 async fn admin_users_list(State(state): State<AppState>, headers: HeaderMap) -> Response {
-    let Some(username) = check_auth(&headers, &state) else {
-        return json_error(StatusCode::UNAUTHORIZED, "Unauthorized");
-    };
-    if username != "admin" {
-        return json_error(StatusCode::FORBIDDEN, "Admin access required");
+    if let Err(resp) = require_role(&headers, &state, Role::Moderator).await {
+        return resp;
     }
-    let users = state.users.lock();
-    let list: Vec<_> = users.users.iter().map(client_user).collect();
+    let users = state.store.list_users().await;
+    let list: Vec<_> = users.iter().map(client_user).collect();
     Json(serde_json::json!({"success": true, "users": list})).into_response()
 }
 
@@ -1439,7 +3218,8 @@ async fn admin_users_list(State(state): State<AppState>, headers: HeaderMap) ->
 struct AdminCreate {
     newUsername: String,
     email: String,
-    password: String,
+    #[serde(default)]
+    password: Option<String>,
 }
 
 async fn admin_users_create(
@@ -1447,11 +3227,8 @@ async fn admin_users_create(
     headers: HeaderMap,
     Json(body): Json<AdminCreate>,
 ) -> Response {
-    let Some(actor) = check_auth(&headers, &state) else {
-        return json_error(StatusCode::UNAUTHORIZED, "Unauthorized");
-    };
-    if actor != "admin" {
-        return json_error(StatusCode::FORBIDDEN, "Admin access required");
+    if let Err(resp) = require_role(&headers, &state, Role::Admin).await {
+        return resp;
     }
     if body.newUsername.trim().len() < 3 || body.newUsername.len() > 32 {
         return json_error(
@@ -1462,7 +3239,72 @@ async fn admin_users_create(
     if !body.email.contains('@') {
         return json_error(StatusCode::BAD_REQUEST, "Email invalid");
     }
-    if body.password.len() < 6 {
+
+    if smtp_configured(&state.cfg) {
+        // flux cu invitație: nu mai creăm contul pe loc, trimitem un link de acceptare
+        let (exists_u, exists_e, exists_inv) = {
+            let users = state.store.list_users().await;
+            let invitations = state.invitations.lock();
+            (
+                users.iter().any(|u| u.username == body.newUsername),
+                users
+                    .iter()
+                    .any(|u| u.email.eq_ignore_ascii_case(&body.email)),
+                invitations
+                    .invitations
+                    .iter()
+                    .any(|i| i.username == body.newUsername || i.email.eq_ignore_ascii_case(&body.email)),
+            )
+        };
+        if exists_u {
+            return json_error(StatusCode::CONFLICT, "Username already exists");
+        }
+        if exists_e {
+            return json_error(StatusCode::CONFLICT, "Email already exists");
+        }
+        if exists_inv {
+            return json_error(StatusCode::CONFLICT, "An invitation is already pending for this user/email");
+        }
+
+        let raw_token = rand_hex(32);
+        let now = now_ms();
+        let invitation = Invitation {
+            username: body.newUsername.clone(),
+            email: body.email.clone(),
+            token_hash: hmac_sign(state.session_secret.as_str(), &raw_token),
+            created_at: now,
+            expires_at: now + INVITE_TTL_SECS * 1000,
+        };
+
+        let invitations_snapshot = {
+            let mut invitations = state.invitations.lock();
+            invitations.invitations.push(invitation);
+            invitations.clone()
+        };
+        let _ = save_invitations(&state.cfg, &invitations_snapshot).await;
+
+        let origin = resolve_public_origin(&state, &headers);
+        let link = format!("{}/invite?token={}", origin, raw_token);
+        if let Err(e) = send_mail(
+            &state.cfg,
+            &body.email,
+            "You've been invited",
+            &format!("You've been invited to create an account. Accept here: {}", link),
+        )
+        .await
+        {
+            eprintln!("send_mail (invite) failed for {}: {}", body.email, e);
+            return json_error(StatusCode::BAD_GATEWAY, "Invitation created but the email could not be sent");
+        }
+
+        return Json(serde_json::json!({"success": true, "invited": true, "email": body.email})).into_response();
+    }
+
+    // fără SMTP configurat: păstrăm comportamentul instant de dinainte
+    let Some(password) = &body.password else {
+        return json_error(StatusCode::BAD_REQUEST, "password required");
+    };
+    if password.len() < 6 {
         return json_error(
             StatusCode::BAD_REQUEST,
             "Password must be at least 6 characters",
@@ -1472,33 +3314,99 @@ async fn admin_users_create(
     // construim user + out înainte de push
     let new_user = User {
         username: body.newUsername.clone(),
-        password_hash: bcrypt::hash(&body.password, 10).unwrap(),
+        password_hash: bcrypt::hash(password, 10).unwrap(),
         email: body.email.clone(),
         created_at: now_ms(),
         role: "user".into(),
-        preferences: Preferences {
-            background: BackgroundPref {
-                kind: "color".into(),
-                value: DEFAULT_BG_COLOR.into(),
-            },
-        },
-        images: vec![],
+        preferences: Preferences::default(),
+        totp_secret: None,
+        pending_totp_secret: None,
+        verified: true,
     };
     let out_val = client_user(&new_user);
 
     // verificări + push + save
-    let (exists_u, exists_e, users_snapshot) = {
-        let mut users = state.users.lock();
-        let exists_u = users.users.iter().any(|u| u.username == new_user.username);
-        let exists_e = users
-            .users
+    let existing = state.store.list_users().await;
+    let exists_u = existing.iter().any(|u| u.username == new_user.username);
+    let exists_e = existing
+        .iter()
+        .any(|u| u.email.eq_ignore_ascii_case(&new_user.email));
+
+    if exists_u {
+        return json_error(StatusCode::CONFLICT, "Username already exists");
+    }
+    if exists_e {
+        return json_error(StatusCode::CONFLICT, "Email already exists");
+    }
+
+    let _ = state.store.upsert_user(new_user).await;
+    Json(serde_json::json!({"success": true, "user": out_val})).into_response()
+}
+
+#[derive(Deserialize)]
+struct InviteAcceptBody {
+    token: String,
+    password: String,
+}
+
+async fn api_invite_accept(
+    State(state): State<AppState>,
+    Json(body): Json<InviteAcceptBody>,
+) -> Response {
+    if body.password.len() < 6 {
+        return json_error(
+            StatusCode::BAD_REQUEST,
+            "Password must be at least 6 characters",
+        );
+    }
+    let token_hash = hmac_sign(state.session_secret.as_str(), &body.token);
+
+    let invitation = {
+        let invitations = state.invitations.lock();
+        invitations
+            .invitations
             .iter()
-            .any(|u| u.email.eq_ignore_ascii_case(&new_user.email));
-        if !exists_u && !exists_e {
-            users.users.push(new_user);
-        }
-        (exists_u, exists_e, users.clone())
+            .find(|i| timing_equal(&i.token_hash, &token_hash))
+            .cloned()
+    };
+    let Some(invitation) = invitation else {
+        return json_error(StatusCode::NOT_FOUND, "Invalid or expired invitation");
+    };
+    if now_ms() > invitation.expires_at {
+        let invitations_snapshot = {
+            let mut invitations = state.invitations.lock();
+            invitations.invitations.retain(|i| !timing_equal(&i.token_hash, &token_hash));
+            invitations.clone()
+        };
+        let _ = save_invitations(&state.cfg, &invitations_snapshot).await;
+        return json_error(StatusCode::GONE, "Invitation expired");
+    }
+
+    let new_user = User {
+        username: invitation.username.clone(),
+        password_hash: bcrypt::hash(&body.password, 10).unwrap(),
+        email: invitation.email.clone(),
+        created_at: now_ms(),
+        role: "user".into(),
+        preferences: Preferences::default(),
+        totp_secret: None,
+        pending_totp_secret: None,
+        verified: true,
+    };
+    let out_val = client_user(&new_user);
+
+    let existing = state.store.list_users().await;
+    let exists_u = existing.iter().any(|u| u.username == new_user.username);
+    let exists_e = existing
+        .iter()
+        .any(|u| u.email.eq_ignore_ascii_case(&new_user.email));
+
+    let invitations_snapshot = {
+        let mut invitations = state.invitations.lock();
+        invitations.invitations.retain(|i| !timing_equal(&i.token_hash, &token_hash));
+        invitations.clone()
     };
+    let _ = save_invitations(&state.cfg, &invitations_snapshot).await;
 
     if exists_u {
         return json_error(StatusCode::CONFLICT, "Username already exists");
@@ -1507,7 +3415,7 @@ async fn admin_users_create(
         return json_error(StatusCode::CONFLICT, "Email already exists");
     }
 
-    let _ = save_users(&state.cfg, &users_snapshot).await;
+    let _ = state.store.upsert_user(new_user).await;
     Json(serde_json::json!({"success": true, "user": out_val})).into_response()
 }
 
@@ -1516,40 +3424,153 @@ async fn admin_users_delete(
     headers: HeaderMap,
     Path(username): Path<String>,
 ) -> Response {
-    let Some(actor) = check_auth(&headers, &state) else {
-        return json_error(StatusCode::UNAUTHORIZED, "Unauthorized");
-    };
-    if actor != "admin" {
-        return json_error(StatusCode::FORBIDDEN, "Admin access required");
+    if let Err(resp) = require_role(&headers, &state, Role::Admin).await {
+        return resp;
     }
     if username == "admin" {
         return json_error(StatusCode::BAD_REQUEST, "Cannot delete admin account");
     }
 
-    let (found, users_snapshot) = {
-        let mut users = state.users.lock();
-        let before = users.users.len();
-        users.users.retain(|u| u.username != username);
-        let found = before != users.users.len();
-        (found, users.clone())
-    };
+    let found = state.store.delete_user(&username).await.unwrap_or(false);
 
     if !found {
         return json_error(StatusCode::NOT_FOUND, "User not found");
     }
 
-    let _ = save_users(&state.cfg, &users_snapshot).await;
     Json(serde_json::json!({"success": true})).into_response()
 }
 
-async fn admin_register_get(State(state): State<AppState>, headers: HeaderMap) -> Response {
-    let Some(actor) = check_auth(&headers, &state) else {
-        return json_error(StatusCode::UNAUTHORIZED, "Unauthorized");
+#[derive(Deserialize)]
+struct RoleUpdateBody {
+    role: String,
+}
+
+async fn admin_users_set_role(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(username): Path<String>,
+    Json(body): Json<RoleUpdateBody>,
+) -> Response {
+    if let Err(resp) = require_role(&headers, &state, Role::Admin).await {
+        return resp;
+    }
+    if body.role != "user" && body.role != "moderator" && body.role != "admin" {
+        return json_error(StatusCode::BAD_REQUEST, "role must be \"user\", \"moderator\" or \"admin\"");
+    }
+    if username == "admin" {
+        return json_error(StatusCode::BAD_REQUEST, "Cannot change the admin account's role");
+    }
+
+    let Some(mut user) = state.store.find_user(&username).await else {
+        return json_error(StatusCode::NOT_FOUND, "User not found");
+    };
+    user.role = body.role.clone();
+    let _ = state.store.upsert_user(user).await;
+
+    Json(serde_json::json!({"success": true, "username": username, "role": body.role})).into_response()
+}
+
+async fn admin_diagnostics(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    if let Err(resp) = require_role(&headers, &state, Role::Admin).await {
+        return resp;
+    }
+
+    let users = state.store.list_users().await;
+    let images = state.store.list_images().await;
+    let tls_active = state.cfg.ssl_key_path.is_some() && state.cfg.ssl_cert_path.is_some();
+    let public_origin = resolve_public_origin(&state, &headers);
+    let upload_token_set = !state.upload_token_hash.lock().is_empty();
+    let upload_dir_free_bytes = fs4::available_space(&state.cfg.upload_dir).unwrap_or(0);
+
+    Json(serde_json::json!({
+        "success": true,
+        "version": env!("CARGO_PKG_VERSION"),
+        "uptime_secs": (now_ms().saturating_sub(state.started_at_ms)) / 1000,
+        "tls_active": tls_active,
+        "public_origin": public_origin,
+        "upload_dir_free_bytes": upload_dir_free_bytes,
+        "user_count": users.len(),
+        "image_count": images.len(),
+        "upload_token_set": upload_token_set,
+    }))
+    .into_response()
+}
+
+/// scrie users.json / images.json / settings.json din store într-o arhivă zip, sincron
+/// (rulat într-un thread de blocking, vezi spawn_blocking mai jos)
+fn write_backup_zip(
+    path: &FsPath,
+    users: &UsersJson,
+    images: &ImagesJson,
+    settings: &SettingsJson,
+) -> io::Result<()> {
+    let file = std::fs::File::create(path)?;
+    let mut zip = zip::ZipWriter::new(file);
+    let options: zip::write::FileOptions<()> =
+        zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+
+    zip.start_file("users.json", options)?;
+    zip.write_all(&serde_json::to_vec_pretty(users)?)?;
+
+    zip.start_file("images.json", options)?;
+    zip.write_all(&serde_json::to_vec_pretty(images)?)?;
+
+    zip.start_file("settings.json", options)?;
+    zip.write_all(&serde_json::to_vec_pretty(settings)?)?;
+
+    zip.finish()?;
+    Ok(())
+}
+
+async fn admin_backup(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    if let Err(resp) = require_role(&headers, &state, Role::Admin).await {
+        return resp;
+    }
+
+    let users = UsersJson { users: state.store.list_users().await };
+    let images = ImagesJson { images: state.store.list_images().await };
+    let settings = state.store.get_settings().await;
+
+    let backups_dir = state.cfg.data_dir.join("backups");
+    if let Err(e) = tfs::create_dir_all(&backups_dir).await {
+        return json_error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string());
+    }
+    let ts = now_ms();
+    let backup_path = backups_dir.join(format!("adedge-backup-{}.zip", ts));
+
+    let blocking_path = backup_path.clone();
+    let zip_result = tokio::task::spawn_blocking(move || {
+        write_backup_zip(&blocking_path, &users, &images, &settings)
+    })
+    .await;
+    if !matches!(zip_result, Ok(Ok(()))) {
+        return json_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to create backup");
+    }
+
+    let bytes = match tfs::read(&backup_path).await {
+        Ok(b) => b,
+        Err(e) => return json_error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
     };
-    if actor != "admin" {
-        return json_error(StatusCode::FORBIDDEN, "Admin access required");
+
+    let disposition = format!("attachment; filename=adedge-backup-{}.zip", ts);
+    (
+        [
+            (header::CONTENT_TYPE, HeaderValue::from_static("application/zip")),
+            (
+                header::CONTENT_DISPOSITION,
+                HeaderValue::from_str(&disposition).unwrap_or_else(|_| HeaderValue::from_static("attachment")),
+            ),
+        ],
+        Body::from(bytes),
+    )
+        .into_response()
+}
+
+async fn admin_register_get(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    if let Err(resp) = require_role(&headers, &state, Role::Admin).await {
+        return resp;
     }
-    let blocked = state.settings.lock().registerBlocked;
+    let blocked = state.store.get_settings().await.registerBlocked;
     Json(serde_json::json!({"blocked": blocked})).into_response()
 }
 
@@ -1563,11 +3584,8 @@ async fn admin_register_set(
     headers: HeaderMap,
     Json(body): Json<RegisterBlockedBody>,
 ) -> Response {
-    let Some(actor) = check_auth(&headers, &state) else {
-        return json_error(StatusCode::UNAUTHORIZED, "Unauthorized");
-    };
-    if actor != "admin" {
-        return json_error(StatusCode::FORBIDDEN, "Admin access required");
+    if let Err(resp) = require_role(&headers, &state, Role::Admin).await {
+        return resp;
     }
 
     let val = match body.blocked {
@@ -1576,12 +3594,9 @@ async fn admin_register_set(
         _ => false,
     };
 
-    let settings_snapshot = {
-        let mut s = state.settings.lock();
-        s.registerBlocked = val;
-        s.clone()
-    };
-    let _ = save_settings(&state.cfg, &settings_snapshot).await;
+    let mut settings = state.store.get_settings().await;
+    settings.registerBlocked = val;
+    let _ = state.store.set_settings(settings).await;
 
     Json(serde_json::json!({
         "success": true,
@@ -1600,9 +3615,10 @@ struct RegisterBody {
 
 async fn public_register(
     State(state): State<AppState>,
+    headers: HeaderMap,
     Json(body): Json<RegisterBody>,
 ) -> Response {
-    if state.settings.lock().registerBlocked {
+    if state.store.get_settings().await.registerBlocked {
         return json_error(
             StatusCode::FORBIDDEN,
             "Registration is currently disabled.",
@@ -1633,34 +3649,12 @@ async fn public_register(
         );
     }
 
+    let needs_verification = smtp_configured(&state.cfg);
+
     // verificări + push + save
-    let (exists_u, exists_e, users_snapshot) = {
-        let mut users = state.users.lock();
-        let exists_u = users.users.iter().any(|u| u.username == uname);
-        let exists_e = users
-            .users
-            .iter()
-            .any(|u| u.email.eq_ignore_ascii_case(email));
-        if !exists_u && !exists_e {
-            let hash = bcrypt::hash(pass, 10).unwrap();
-            let user = User {
-                username: uname.into(),
-                password_hash: hash,
-                email: email.into(),
-                created_at: now_ms(),
-                role: "user".into(),
-                preferences: Preferences {
-                    background: BackgroundPref {
-                        kind: "color".into(),
-                        value: DEFAULT_BG_COLOR.into(),
-                    },
-                },
-                images: vec![],
-            };
-            users.users.push(user);
-        }
-        (exists_u, exists_e, users.clone())
-    };
+    let existing = state.store.list_users().await;
+    let exists_u = existing.iter().any(|u| u.username == uname);
+    let exists_e = existing.iter().any(|u| u.email.eq_ignore_ascii_case(email));
 
     if exists_u {
         return json_error(StatusCode::CONFLICT, "Username already exists.");
@@ -1669,9 +3663,86 @@ async fn public_register(
         return json_error(StatusCode::CONFLICT, "Email already exists.");
     }
 
-    let _ = save_users(&state.cfg, &users_snapshot).await;
-    Json(serde_json::json!({"ok": true, "message": "User created.", "user": {"username": uname, "email": email, "role": "user"} }))
-        .into_response()
+    let hash = bcrypt::hash(pass, 10).unwrap();
+    let user = User {
+        username: uname.into(),
+        password_hash: hash,
+        email: email.into(),
+        created_at: now_ms(),
+        role: "user".into(),
+        preferences: Preferences::default(),
+        totp_secret: None,
+        pending_totp_secret: None,
+        verified: !needs_verification,
+    };
+    let _ = state.store.upsert_user(user).await;
+
+    let mut email_sent = true;
+    if needs_verification {
+        let exp = now_s_f64() as u64 + EMAIL_VERIFY_TTL_SECS;
+        let sig = sign_email_verification(state.session_secret.as_str(), uname, exp);
+        let origin = resolve_public_origin(&state, &headers);
+        let link = format!(
+            "{}/api/account/verify?username={}&exp={}&sig={}",
+            origin,
+            urlencoding::encode(uname),
+            exp,
+            sig
+        );
+        if let Err(e) = send_mail(
+            &state.cfg,
+            email,
+            "Verify your account",
+            &format!("Confirm your account by visiting: {}", link),
+        )
+        .await
+        {
+            eprintln!("send_mail (verify) failed for {}: {}", email, e);
+            email_sent = false;
+        }
+    }
+
+    let message = if !needs_verification {
+        "User created."
+    } else if email_sent {
+        "User created. Check your email to verify your account."
+    } else {
+        "User created, but the verification email could not be sent. Contact an admin."
+    };
+
+    Json(serde_json::json!({
+        "ok": true,
+        "email_sent": email_sent,
+        "message": message,
+        "user": {"username": uname, "email": email, "role": "user"}
+    }))
+    .into_response()
+}
+
+#[derive(Deserialize)]
+struct VerifyAccountQuery {
+    username: String,
+    exp: u64,
+    sig: String,
+}
+
+async fn api_account_verify(
+    State(state): State<AppState>,
+    Query(query): Query<VerifyAccountQuery>,
+) -> Response {
+    if now_s_f64() as u64 > query.exp {
+        return json_error(StatusCode::GONE, "Verification link expired");
+    }
+    if !verify_email_verification(state.session_secret.as_str(), &query.username, query.exp, &query.sig) {
+        return json_error(StatusCode::FORBIDDEN, "Invalid verification link");
+    }
+
+    let Some(mut user) = state.store.find_user(&query.username).await else {
+        return json_error(StatusCode::NOT_FOUND, "User not found");
+    };
+    user.verified = true;
+    let _ = state.store.upsert_user(user).await;
+    Json(serde_json::json!({"success": true, "verified": true})).into_response()
 }
 
 // ========================= ShareX .sxcu =========================
@@ -1720,49 +3791,22 @@ async fn generate_sxcu(state: AppState, headers: HeaderMap) -> Response {
         return json_error(StatusCode::UNAUTHORIZED, "Unauthorized");
     };
 
-    // 2) citește email fără să ții lock la await
-    let email = {
-        let users = state.users.lock();
-        find_user(&users, &username)
-            .map(|u| u.email.clone())
-            .unwrap_or_default()
-    }; // <- lock eliberat aici
-
-    // 3) vezi dacă ai deja un token în memorie (NU ține lock peste await)
-    let maybe_plain: Option<String> = {
-        let g = state.initial_upload_token_plain.lock();
-        g.clone()
-    }; // <- lock eliberat aici
-
-    // 4) fie folosești tokenul existent, fie creezi unul nou (cu hash în thread pool)
-    let token = if let Some(t) = maybe_plain {
-        t
-    } else {
-        let new_tok = rand_hex(24);
-
-        // calculează hash-ul într-un thread de blocking
-        let new_hash = match tokio::task::spawn_blocking({
-            let t = new_tok.clone();
-            move || bcrypt::hash(t, 10)
-        })
+    // 2) citește email-ul din store
+    let email = state
+        .store
+        .find_user(&username)
         .await
-        {
-            Ok(Ok(h)) => h,
-            _ => return json_error(StatusCode::INTERNAL_SERVER_ERROR, "Could not hash token"),
-        };
-
-        // scrie hash-ul (scurt lock, fără await)
-        {
-            let mut h = state.upload_token_hash.lock();
-            *h = new_hash;
-        }
-        {
-            let mut p = state.initial_upload_token_plain.lock();
-            *p = Some(new_tok.clone());
-        }
+        .map(|u| u.email)
+        .unwrap_or_default();
 
-        new_tok
-    };
+    // 3) token de upload scurt-trăit, scopat la acest user — JWT semnat cu SESSION_SECRET,
+    // nu mai folosim tokenul static din initial_upload_token_plain pentru .sxcu-uri noi
+    let iat = now_s_f64() as u64;
+    let exp = iat + UPLOAD_JWT_TTL_SECS;
+    let token = jwt_encode(
+        state.session_secret.as_str(),
+        &serde_json::json!({"sub": username, "scope": "upload", "iat": iat, "exp": exp}),
+    );
 
     // 5) origin corect (https dacă ai cheie+cert în .env, altfel http; sau PUBLIC_ORIGIN)
     let origin = resolve_public_origin(&state, &headers);
@@ -1796,8 +3840,36 @@ fn json_error(status: StatusCode, msg: &str) -> Response {
     (status, Json(serde_json::json!({"success": false, "error": msg}))).into_response()
 }
 
-async fn redirect_http(Host(host): Host, OriginalUri(uri): OriginalUri) -> Redirect {
-    Redirect::permanent(&format!("https://{}{}", host, uri))
+async fn redirect_http(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Host(host): Host,
+    OriginalUri(uri): OriginalUri,
+) -> Response {
+    let host_header = if state.cfg.trust_forwarded_host {
+        headers
+            .get("x-forwarded-host")
+            .and_then(|v| v.to_str().ok())
+            .map(|s| s.to_string())
+            .unwrap_or(host)
+    } else {
+        host
+    };
+    // păstrăm doar hostname-ul, portul vine din https_port (nu din portul HTTP original)
+    let host_only = host_header.rsplit_once(':').map(|(h, _)| h).unwrap_or(&host_header);
+    let target = if state.cfg.https_port == 443 {
+        // portul implicit HTTPS nu trebuie scris explicit în URL
+        format!("https://{}{}", host_only, uri)
+    } else {
+        format!("https://{}:{}{}", host_only, state.cfg.https_port, uri)
+    };
+
+    if state.cfg.redirect_permanent {
+        // Redirect::permanent din axum trimite 308; aici vrem 301 Moved Permanently
+        (StatusCode::MOVED_PERMANENTLY, [(header::LOCATION, target)]).into_response()
+    } else {
+        Redirect::temporary(&target).into_response()
+    }
 }
 
 // ========================= Pagini de bază =========================
@@ -1842,7 +3914,7 @@ async fn reg_html_404() -> Response {
 }
 
 async fn register_page(State(state): State<AppState>) -> Response {
-    if state.settings.lock().registerBlocked {
+    if state.store.get_settings().await.registerBlocked {
         return (StatusCode::FORBIDDEN, "").into_response();
     }
     static_file(&state.cfg.public_dir.join("register.html")).await
@@ -1852,9 +3924,54 @@ async fn healthz() -> Response {
     Json(serde_json::json!({"ok": true})).into_response()
 }
 
+// ========================= Coduri de ieșire =========================
+// fiecare clasă de eșec la pornire primește propriul cod, ca systemd/Docker/supervisoarele
+// să poată scripta politici de restart/backoff în funcție de motiv
+#[derive(Debug)]
+enum StartupError {
+    Config(String),  // .env / fișiere de date invalide sau ilizibile
+    Tls(String),     // certificat/cheie TLS inexistente sau imposibil de parsat
+    Bind(String),    // socket-ul nu a putut fi legat (ex. EADDRINUSE)
+    Runtime(String), // orice altă eroare apărută în timpul rulării serverului
+}
+
+impl StartupError {
+    fn exit_code(&self) -> u8 {
+        match self {
+            StartupError::Config(_) => 2,
+            StartupError::Tls(_) => 3,
+            StartupError::Bind(_) => 4,
+            StartupError::Runtime(_) => 1,
+        }
+    }
+}
+
+impl std::fmt::Display for StartupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StartupError::Config(m) => write!(f, "config error: {}", m),
+            StartupError::Tls(m) => write!(f, "TLS error: {}", m),
+            StartupError::Bind(m) => write!(f, "bind error: {}", m),
+            StartupError::Runtime(m) => write!(f, "runtime error: {}", m),
+        }
+    }
+}
+
+impl std::error::Error for StartupError {}
+
 // ========================= Start server =========================
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+async fn main() -> std::process::ExitCode {
+    match run().await {
+        Ok(()) => std::process::ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("ADEdge nu a putut porni: {}", e);
+            std::process::ExitCode::from(e.exit_code())
+        }
+    }
+}
+
+async fn run() -> Result<(), StartupError> {
     // .env bootstrap
     let env_path = FsPath::new(".env");
     let (initial_upload_token_plain, env_kv) = ensure_env(env_path);
@@ -1878,6 +3995,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let users_file = data_dir.join("users.json");
     let images_file = data_dir.join("images.json");
     let settings_file = data_dir.join("settings.json");
+    let invitations_file = data_dir.join("invitations.json");
 
     let max_upload_bytes = env_kv
         .get("MAX_UPLOAD_BYTES")
@@ -1900,12 +4018,52 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .get("SSL_CERT_PATH")
         .filter(|s| !s.is_empty())
         .map(PathBuf::from);
+    let allowed_upload_mimes = env_kv
+        .get("ALLOWED_UPLOAD_MIMES")
+        .filter(|s| !s.is_empty())
+        .map(|s| s.split(',').map(|m| m.trim().to_string()).collect())
+        .unwrap_or_else(|| DEFAULT_ALLOWED_UPLOAD_MIMES.iter().map(|s| s.to_string()).collect());
+    let encrypt_at_rest = env_kv
+        .get("ENCRYPT_AT_REST")
+        .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+        .unwrap_or(false);
+
+    let smtp_host = env_kv.get("SMTP_HOST").filter(|s| !s.is_empty()).cloned();
+    let smtp_port = env_kv.get("SMTP_PORT").and_then(|v| v.parse().ok());
+    let smtp_user = env_kv.get("SMTP_USER").filter(|s| !s.is_empty()).cloned();
+    let smtp_pass = env_kv.get("SMTP_PASS").filter(|s| !s.is_empty()).cloned();
+    let smtp_from = env_kv.get("SMTP_FROM").filter(|s| !s.is_empty()).cloned();
+    let admin_token = env_kv.get("ADMIN_TOKEN").filter(|s| !s.is_empty()).cloned();
+    let shutdown_grace_secs = env_kv
+        .get("SHUTDOWN_GRACE_SECS")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_SHUTDOWN_GRACE_SECS);
+    let tls_hot_reload = env_kv
+        .get("TLS_HOT_RELOAD")
+        .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+        .unwrap_or(false);
+    let tls_reload_interval_secs = env_kv
+        .get("TLS_RELOAD_INTERVAL_SECS")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_TLS_RELOAD_INTERVAL_SECS);
+    let redirect_permanent = env_kv
+        .get("HTTPS_REDIRECT_PERMANENT")
+        .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+        .unwrap_or(false);
+    let trust_forwarded_host = env_kv
+        .get("TRUST_FORWARDED_HOST")
+        .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+        .unwrap_or(false);
 
     if !upload_dir.exists() {
-        tfs::create_dir_all(&upload_dir).await?;
+        tfs::create_dir_all(&upload_dir)
+            .await
+            .map_err(|e| StartupError::Config(format!("nu pot crea {}: {}", upload_dir.display(), e)))?;
     }
     if !background_dir.exists() {
-        tfs::create_dir_all(&background_dir).await?;
+        tfs::create_dir_all(&background_dir)
+            .await
+            .map_err(|e| StartupError::Config(format!("nu pot crea {}: {}", background_dir.display(), e)))?;
     }
 
     let cfg = Config {
@@ -1919,17 +4077,40 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         users_file,
         images_file,
         settings_file,
+        invitations_file,
         max_upload_bytes,
         rate_tokens,
         rate_refill,
         ssl_key_path,
         ssl_cert_path,
+        allowed_upload_mimes,
+        encrypt_at_rest,
+        smtp_host,
+        smtp_port,
+        smtp_user,
+        smtp_pass,
+        smtp_from,
+        admin_token,
+        shutdown_grace_secs,
+        tls_hot_reload,
+        tls_reload_interval_secs,
+        redirect_permanent,
+        trust_forwarded_host,
     };
 
+    let cfg = Arc::new(cfg);
+
     let admin_pass_env = env_kv.get("ADMIN_PASSWORD").cloned();
-    let first_admin_pass = ensure_data_and_admin(&cfg, admin_pass_env).await?;
+    let first_admin_pass = ensure_data_and_admin(&cfg, admin_pass_env)
+        .await
+        .map_err(|e| StartupError::Config(format!("inițializare date admin eșuată: {}", e)))?;
 
-    let (users, images, settings) = load_all(&cfg).await?;
+    let (users, images, settings, invitations) = load_all(&cfg)
+        .await
+        .map_err(|e| StartupError::Config(format!("citire fișiere de date eșuată: {}", e)))?;
+    let store = build_store(&cfg, &env_kv, users, images, settings)
+        .await
+        .map_err(|e| StartupError::Config(format!("inițializare store eșuată: {}", e)))?;
 
     let session_secret = env_kv
         .get("SESSION_SECRET")
@@ -1941,22 +4122,22 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .unwrap_or_default();
 
     let state = AppState {
-        cfg: Arc::new(cfg),
+        cfg: cfg.clone(),
         session_secret: Arc::new(session_secret),
         upload_token_hash: Arc::new(Mutex::new(upload_token_hash)),
         initial_upload_token_plain: Arc::new(Mutex::new(initial_upload_token_plain)),
         initial_admin_pass_plain: Arc::new(Mutex::new(first_admin_pass)),
-        users: Arc::new(Mutex::new(users)),
-        images: Arc::new(Mutex::new(images)),
-        settings: Arc::new(Mutex::new(settings)),
+        store,
+        invitations: Arc::new(Mutex::new(invitations)),
         rate: Arc::new(Mutex::new(HashMap::new())),
+        started_at_ms: now_ms(),
     };
 
     // logging
     println!("Uploads dir: {}", state.cfg.upload_dir.display());
     println!(
         "Registration lock: {}",
-        if state.settings.lock().registerBlocked {
+        if state.store.get_settings().await.registerBlocked {
             "BLOCKED"
         } else {
             "OPEN"
@@ -1998,15 +4179,28 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .route("/api/settings", post(api_settings))
         .route("/api/account/settings", post(api_settings))
         .route("/api/account/email", post(api_update_email))
+        .route("/api/account/2fa/enable", post(api_2fa_enable))
+        .route("/api/account/2fa/verify", post(api_2fa_verify))
+        .route("/api/account/2fa/disable", post(api_2fa_disable))
         .route("/api/account/background/templates", get(api_bg_templates))
         // images
         .route("/api/images", get(api_images_list).delete(api_images_delete_by_filename))
         .route("/api/images/:id", delete(api_images_delete_by_id))
+        .route("/api/images/embed", post(api_images_update_embed))
+        .route("/api/images/sensitive", post(api_images_update_sensitive))
+        .route("/api/images/visibility", post(api_images_update_visibility))
+        .route("/api/images/sign", post(api_images_sign))
+        .route("/api/oembed/:filename", get(api_oembed))
         .route("/api/upload", post(api_upload_dashboard))
         // admin
         .route("/api/account/users", get(admin_users_list).post(admin_users_create))
         .route("/api/account/users/:username", delete(admin_users_delete))
+        .route("/api/account/users/:username/role", post(admin_users_set_role))
         .route("/api/admin/register", get(admin_register_get).post(admin_register_set))
+        .route("/api/admin/diagnostics", get(admin_diagnostics))
+        .route("/api/admin/backup", post(admin_backup))
+        .route("/api/invite/accept", post(api_invite_accept))
+        .route("/api/account/verify", get(api_account_verify))
         // register public
         .route("/register.html", get(reg_html_404))
         .route("/register", get(register_page).post(public_register))
@@ -2045,51 +4239,163 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let have_tls = state.cfg.ssl_key_path.is_some() && state.cfg.ssl_cert_path.is_some();
 
+    let grace = Duration::from_secs(state.cfg.shutdown_grace_secs);
+
+    // dacă systemd ne-a pasat fd-uri deja legate (socket-activation), le adoptăm în loc
+    // să legăm noi socket-uri — altfel cădem pe bind-ul obișnuit
+    let inherited_fds = systemd_listen_fds();
+    if inherited_fds > 0 {
+        println!("systemd socket-activation: {} fd(-uri) moștenite", inherited_fds);
+    }
+
     if have_tls {
         let cert_path = state.cfg.ssl_cert_path.clone().unwrap();
         let key_path  = state.cfg.ssl_key_path.clone().unwrap();
 
-        let tls_config = RustlsConfig::from_pem_file(cert_path.clone(), key_path.clone()).await?;
+        let tls_config = RustlsConfig::from_pem_file(cert_path.clone(), key_path.clone())
+            .await
+            .map_err(|e| StartupError::Tls(format!("nu pot încărca {}/{}: {}", cert_path.display(), key_path.display(), e)))?;
+
+        if state.cfg.tls_hot_reload {
+            spawn_tls_hot_reload(
+                tls_config.clone(),
+                cert_path.clone(),
+                key_path.clone(),
+                Duration::from_secs(state.cfg.tls_reload_interval_secs),
+            );
+        }
 
         println!("HTTPS server pornit: https://localhost:{}", state.cfg.https_port);
 
+        // handle-uri separate pentru drenare grațioasă (axum_server::Handle), câte unul per listener
+        let https_handle = axum_server::Handle::new();
+        let http_handle = axum_server::Handle::new();
+        spawn_graceful_shutdown(vec![https_handle.clone(), http_handle.clone()], grace);
+
         // HTTPS serve app-ul tău
-        let https_srv = axum_server::bind_rustls(https_addr, tls_config)
+        let https_listener = bind_listener(https_addr, 0, inherited_fds)
+            .map_err(|e| classify_serve_error(https_addr, e))?;
+        let https_srv = axum_server::from_tcp_rustls(https_listener, tls_config)
+            .handle(https_handle)
             .serve(app.clone().into_make_service_with_connect_info::<SocketAddr>());
 
         // HTTP → redirect către HTTPS
-        let redirect_app = Router::new().fallback(redirect_http);
-        let http_listener = tokio::net::TcpListener::bind(http_addr).await?;
+        let redirect_app = Router::new().fallback(redirect_http).with_state(state.clone());
         println!("HTTP redirect activ:  http://localhost:{} → HTTPS", state.cfg.http_port);
-        let http_srv = axum::serve(
-            http_listener,
-            redirect_app.into_make_service_with_connect_info::<SocketAddr>(),
-        );
-
-        tokio::select! {
-            res = https_srv => { if let Err(e) = res { eprintln!("HTTPS error: {}", e); } },
-            res = http_srv  => { if let Err(e) = res { eprintln!("HTTP redirect error: {}", e); } },
-            _ = shutdown_signal() => { println!("Received shutdown, closing..."); }
-        }
+        let http_listener = bind_listener(http_addr, 1, inherited_fds)
+            .map_err(|e| classify_serve_error(http_addr, e))?;
+        let http_srv = axum_server::from_tcp(http_listener)
+            .handle(http_handle)
+            .serve(redirect_app.into_make_service_with_connect_info::<SocketAddr>());
+
+        let (https_res, http_res) = tokio::join!(https_srv, http_srv);
+        https_res.map_err(|e| classify_serve_error(https_addr, e))?;
+        http_res.map_err(|e| classify_serve_error(http_addr, e))?;
     } else {
         // Fără TLS în .env → doar HTTP
-        let http_listener = tokio::net::TcpListener::bind(http_addr).await?;
         println!("HTTP server pornit:  http://localhost:{}", state.cfg.http_port);
 
-        let http_server = axum::serve(
-            http_listener,
-            app.into_make_service_with_connect_info::<SocketAddr>(),
-        );
+        let handle = axum_server::Handle::new();
+        spawn_graceful_shutdown(vec![handle.clone()], grace);
 
-        tokio::select! {
-            res = http_server => { if let Err(e) = res { eprintln!("HTTP error: {}", e); } },
-            _ = shutdown_signal() => { println!("Received shutdown, closing..."); }
-        }
+        let http_listener = bind_listener(http_addr, 0, inherited_fds)
+            .map_err(|e| classify_serve_error(http_addr, e))?;
+        axum_server::from_tcp(http_listener)
+            .handle(handle)
+            .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+            .await
+            .map_err(|e| classify_serve_error(http_addr, e))?;
     }
 
     Ok(())
 }
 
+// ========================= systemd socket-activation =========================
+// convenția sd_listen_fds: systemd setează LISTEN_PID (pid-ul procesului țintă) și
+// LISTEN_FDS (numărul de fd-uri moștenite, începând de la fd 3) înainte de exec
+const SD_LISTEN_FDS_START: i32 = 3;
+
+/// câte fd-uri ne-a pasat systemd, sau 0 dacă nu rulăm sub socket-activation
+/// (LISTEN_PID nu se potrivește cu pid-ul nostru sau lipsește)
+fn systemd_listen_fds() -> usize {
+    let pid_matches = std::env::var("LISTEN_PID")
+        .ok()
+        .and_then(|v| v.parse::<u32>().ok())
+        .map(|pid| pid == std::process::id())
+        .unwrap_or(false);
+    if !pid_matches {
+        return 0;
+    }
+    std::env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(0)
+}
+
+/// preia al `index`-lea fd moștenit de la systemd (dacă e disponibil), altfel leagă normal pe `addr` —
+/// păstrează socket-ul deschis peste restart și elimină race-ul de bind la upgrade-uri
+fn bind_listener(addr: SocketAddr, index: usize, inherited: usize) -> io::Result<std::net::TcpListener> {
+    let listener = if index < inherited {
+        let fd = SD_LISTEN_FDS_START + index as i32;
+        println!("Preiau fd-ul {} moștenit de la systemd (socket-activation) pentru {}", fd, addr);
+        unsafe { std::net::TcpListener::from_raw_fd(fd) }
+    } else {
+        std::net::TcpListener::bind(addr)?
+    };
+    listener.set_nonblocking(true)?;
+    Ok(listener)
+}
+
+/// distinge eșecul de bind (ex. EADDRINUSE) de alte erori apărute în timpul servirii,
+/// pentru a putea întoarce un cod de ieșire distinct
+fn classify_serve_error(addr: SocketAddr, e: io::Error) -> StartupError {
+    match e.kind() {
+        io::ErrorKind::AddrInUse | io::ErrorKind::AddrNotAvailable | io::ErrorKind::PermissionDenied => {
+            StartupError::Bind(format!("nu pot lega {}: {}", addr, e))
+        }
+        _ => StartupError::Runtime(format!("{}: {}", addr, e)),
+    }
+}
+
 async fn shutdown_signal() {
     let _ = signal::ctrl_c().await;
 }
+
+/// așteaptă semnalul de shutdown, apoi cere tuturor handle-urilor să dreneze conexiunile
+/// active cel mult `grace`, în loc să le întrerupă brusc
+fn spawn_graceful_shutdown(handles: Vec<axum_server::Handle>, grace: Duration) {
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        println!("Received shutdown, draining connections (up to {}s)...", grace.as_secs());
+        for handle in handles {
+            handle.graceful_shutdown(Some(grace));
+        }
+    });
+}
+
+/// pornește un task de fundal care pollează mtime-ul fișierelor de certificat/cheie TLS și,
+/// la schimbare, reîncarcă `RustlsConfig` pe loc — listener-ele deja pornite preiau noul
+/// certificat la următorul handshake, fără să fie nevoie de restart
+fn spawn_tls_hot_reload(tls_config: RustlsConfig, cert_path: PathBuf, key_path: PathBuf, interval: Duration) {
+    tokio::spawn(async move {
+        let mut last_seen = tls_cert_mtime(&cert_path, &key_path).await;
+        loop {
+            tokio::time::sleep(interval).await;
+            let current = tls_cert_mtime(&cert_path, &key_path).await;
+            if current != last_seen {
+                match tls_config.reload_from_pem_file(&cert_path, &key_path).await {
+                    Ok(()) => println!("TLS: certificat reîncărcat ({})", cert_path.display()),
+                    Err(e) => eprintln!("TLS: reîncărcare eșuată pentru {}: {}", cert_path.display(), e),
+                }
+                last_seen = current;
+            }
+        }
+    });
+}
+
+/// mtime-ul combinat al certificatului și cheii, folosit ca fingerprint pt. detectarea reînnoirii
+async fn tls_cert_mtime(cert_path: &PathBuf, key_path: &PathBuf) -> Option<(SystemTime, SystemTime)> {
+    let cert_mtime = tfs::metadata(cert_path).await.ok()?.modified().ok()?;
+    let key_mtime = tfs::metadata(key_path).await.ok()?.modified().ok()?;
+    Some((cert_mtime, key_mtime))
+}